@@ -0,0 +1,132 @@
+use crate::error::Error;
+use crate::jwk::JsonWebKey;
+use crate::key_policy::KeyPolicy;
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::verify::X509VerifyParam;
+use openssl::x509::{X509StoreContext, X509};
+use std::sync::Arc;
+
+/// Which `x5c` certificate chains are trusted to sign tokens. The chain has to lead to one of
+/// the trust anchors, every certificate in it has to be within its validity dates, and the
+/// token has to come from one of the policy's issuers.
+#[derive(Clone, Default)]
+pub struct CertificateChainPolicy {
+    trust_anchors: Vec<X509>,
+    issuers: Vec<String>,
+    hostname: Option<String>,
+    subject_common_name: Option<String>,
+    key_policy: KeyPolicy,
+    // Rebuilt whenever the anchors or the hostname change, instead of for every token. Only
+    // missing while neither has been set, and then no chain is trusted.
+    store: Option<Arc<X509Store>>,
+}
+
+impl CertificateChainPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_trust_anchor(mut self, certificate: X509) -> Result<Self, Error> {
+        self.trust_anchors.push(certificate);
+        self.rebuild_store()
+    }
+
+    /// Adds every certificate in a PEM bundle as a trust anchor.
+    pub fn add_trust_anchor_pem(mut self, pem: &[u8]) -> Result<Self, Error> {
+        let certificates = X509::stack_from_pem(pem).map_err(|_| Error::InvalidCertificate)?;
+        if certificates.is_empty() {
+            return Err(Error::InvalidCertificate);
+        }
+        self.trust_anchors.extend(certificates);
+        self.rebuild_store()
+    }
+
+    /// Accepts chain-signed tokens from `issuer`. Without any issuer every chain-signed token is
+    /// rejected, so a certificate that chains to a trust anchor cannot pass for Google. The
+    /// client has to accept the issuer as well, see `GenericClientBuilder::add_issuer`.
+    pub fn add_issuer(mut self, issuer: &str) -> Self {
+        self.issuers.push(issuer.to_owned());
+        self
+    }
+
+    /// Requires the leaf certificate to be valid for `hostname`.
+    pub fn with_hostname(mut self, hostname: &str) -> Result<Self, Error> {
+        self.hostname = Some(hostname.to_owned());
+        self.rebuild_store()
+    }
+
+    /// Requires the common name in the leaf certificate's subject to be `name`.
+    pub fn with_subject_common_name(mut self, name: &str) -> Self {
+        self.subject_common_name = Some(name.to_owned());
+        self
+    }
+
+    /// Requires the leaf certificate's key to meet `policy`, instead of the default `KeyPolicy`.
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        self.key_policy = policy;
+        self
+    }
+
+    fn rebuild_store(mut self) -> Result<Self, Error> {
+        let store = self.build_store().map_err(|_| Error::InvalidCertificate)?;
+        self.store = Some(Arc::new(store));
+        Ok(self)
+    }
+
+    fn build_store(&self) -> Result<X509Store, ErrorStack> {
+        let mut store = X509StoreBuilder::new()?;
+        for anchor in &self.trust_anchors {
+            store.add_cert(anchor.clone())?;
+        }
+        if let Some(ref hostname) = self.hostname {
+            let mut param = X509VerifyParam::new()?;
+            param.set_host(hostname)?;
+            store.set_param(&param)?;
+        }
+        Ok(store.build())
+    }
+
+    /// Validates a chain of base64 DER certificates, leaf first, and returns the leaf's key.
+    pub(crate) fn leaf_key(&self, key_id: &str, chain: &[String]) -> Result<JsonWebKey, Error> {
+        let mut certificates = chain
+            .iter()
+            .map(|certificate| Ok(X509::from_der(&base64::decode(certificate)?)?))
+            .collect::<Result<Vec<X509>, Error>>()?;
+        if certificates.is_empty() {
+            return Err(Error::InvalidToken);
+        }
+        let leaf = certificates.remove(0);
+
+        let store = self.store.as_ref().ok_or(Error::InvalidToken)?;
+        let mut intermediates = Stack::new()?;
+        for certificate in certificates {
+            intermediates.push(certificate)?;
+        }
+        let mut context = X509StoreContext::new()?;
+        let trusted = context.init(store, &leaf, &intermediates, |context| {
+            context.verify_cert()
+        })?;
+        if !trusted {
+            return Err(Error::InvalidToken);
+        }
+
+        if let Some(ref name) = self.subject_common_name {
+            let matches = leaf
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .any(|entry| entry.data().as_slice() == name.as_bytes());
+            if !matches {
+                return Err(Error::InvalidToken);
+            }
+        }
+        let mut key = JsonWebKey::from_public_key(key_id, leaf.public_key()?)?;
+        if !self.key_policy.accepts(&key) {
+            return Err(Error::InvalidToken);
+        }
+        key.bind_issuers(&self.issuers);
+        Ok(key)
+    }
+}
@@ -1,30 +1,45 @@
+use crate::certificate_chain::CertificateChainPolicy;
+use crate::diagnose::{Diagnosis, DiagnosticReport};
 use crate::error::Error;
+use crate::key_archive::KeyArchive;
 #[cfg(feature = "async")]
 use crate::key_provider::AsyncKeyProvider;
 use crate::key_provider::GoogleKeyProvider;
 #[cfg(feature = "blocking")]
 use crate::key_provider::KeyProvider;
-use crate::token::IdPayload;
+use crate::key_status::{KeyHealth, KeyStatus};
+use crate::refresher;
 use crate::token::Token;
+use crate::token::{BorrowedToken, IdPayload};
 use crate::unverified_token::UnverifiedToken;
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 pub type Client = GenericClient<GoogleKeyProvider>;
 
+const GOOGLE_ISSUERS: [&str; 2] = ["https://accounts.google.com", "accounts.google.com"];
+
 pub struct GenericClientBuilder<KP> {
     client_id: String,
-    key_provider: Arc<Mutex<KP>>,
+    key_provider: Arc<KP>,
     check_expiration: bool,
+    issuers: Vec<String>,
+    certificate_chain_policy: Option<Arc<CertificateChainPolicy>>,
 }
 
 impl<KP: Default> GenericClientBuilder<KP> {
     pub fn new(client_id: &str) -> GenericClientBuilder<KP> {
         GenericClientBuilder::<KP> {
             client_id: client_id.to_owned(),
-            key_provider: Arc::new(Mutex::new(KP::default())),
+            key_provider: Arc::new(KP::default()),
             check_expiration: true,
+            issuers: GOOGLE_ISSUERS
+                .iter()
+                .map(|&issuer| issuer.to_owned())
+                .collect(),
+            certificate_chain_policy: None,
         }
     }
 }
@@ -33,10 +48,25 @@ impl<KP> GenericClientBuilder<KP> {
     pub fn custom_key_provider<T>(self, provider: T) -> GenericClientBuilder<T> {
         GenericClientBuilder {
             client_id: self.client_id,
-            key_provider: Arc::new(Mutex::new(provider)),
+            key_provider: Arc::new(provider),
             check_expiration: self.check_expiration,
+            issuers: self.issuers,
+            certificate_chain_policy: self.certificate_chain_policy,
         }
     }
+    /// Accepts tokens from `issuer` in addition to Google. Use a provider that binds its keys
+    /// to their issuer, like `CompositeKeyProvider`, so that each issuer's keys can only
+    /// verify its own tokens.
+    pub fn add_issuer(mut self, issuer: &str) -> Self {
+        self.issuers.push(issuer.to_owned());
+        self
+    }
+    /// Verifies tokens that carry an `x5c` certificate chain against the chain's leaf
+    /// certificate, instead of looking their key up in the key provider.
+    pub fn certificate_chain_policy(mut self, policy: CertificateChainPolicy) -> Self {
+        self.certificate_chain_policy = Some(Arc::new(policy));
+        self
+    }
     pub fn unsafe_ignore_expiration(mut self) -> Self {
         self.check_expiration = false;
         self
@@ -46,14 +76,30 @@ impl<KP> GenericClientBuilder<KP> {
             client_id: self.client_id,
             key_provider: self.key_provider,
             check_expiration: self.check_expiration,
+            issuers: self.issuers,
+            certificate_chain_policy: self.certificate_chain_policy,
         }
     }
 }
 
 pub struct GenericClient<T> {
     client_id: String,
-    key_provider: Arc<Mutex<T>>,
+    key_provider: Arc<T>,
     check_expiration: bool,
+    issuers: Vec<String>,
+    certificate_chain_policy: Option<Arc<CertificateChainPolicy>>,
+}
+
+impl<KP> Clone for GenericClient<KP> {
+    fn clone(&self) -> Self {
+        GenericClient {
+            client_id: self.client_id.clone(),
+            key_provider: self.key_provider.clone(),
+            check_expiration: self.check_expiration,
+            issuers: self.issuers.clone(),
+            certificate_chain_policy: self.certificate_chain_policy.clone(),
+        }
+    }
 }
 
 impl<KP: Default> GenericClient<KP> {
@@ -65,24 +111,127 @@ impl<KP: Default> GenericClient<KP> {
     }
 }
 
+impl<KP> GenericClient<KP> {
+    /// Verifies a token, possibly long expired, against the keys that were published when it was
+    /// issued. Expiration is not checked, the audience and issuer are.
+    pub fn verify_archived_token_with_payload<P, A>(
+        &self,
+        token_string: &str,
+        archive: &A,
+    ) -> Result<Token<P>, Error>
+    where
+        for<'a> P: Deserialize<'a>,
+        A: KeyArchive + ?Sized,
+    {
+        let unverified_token =
+            UnverifiedToken::<P>::validate(token_string, false, &self.client_id, &self.issuers)?;
+        unverified_token.verify_archived(archive)
+    }
+
+    pub fn verify_archived_id_token<A: KeyArchive + ?Sized>(
+        &self,
+        token_string: &str,
+        archive: &A,
+    ) -> Result<Token<IdPayload>, Error> {
+        self.verify_archived_token_with_payload(token_string, archive)
+    }
+
+    fn start_diagnosis(&self, token_string: &str) -> Diagnosis {
+        Diagnosis::new(
+            token_string,
+            self.check_expiration,
+            &self.client_id,
+            &self.issuers,
+        )
+    }
+}
+
+impl GenericClient<GoogleKeyProvider> {
+    /// Keeps the keys fresh from a background thread, so verification never waits on a download.
+    /// The thread stops once the client has been dropped.
+    #[cfg(feature = "blocking")]
+    pub fn spawn_key_refresher(&self) -> std::thread::JoinHandle<()> {
+        refresher::spawn_refresher(Arc::downgrade(&self.key_provider))
+    }
+
+    /// Same as `spawn_key_refresher`, but runs as a task on the current tokio runtime.
+    #[cfg(feature = "async")]
+    pub fn spawn_key_refresher_async(&self) -> tokio::task::JoinHandle<()> {
+        refresher::spawn_refresher_async(Arc::downgrade(&self.key_provider))
+    }
+
+    pub fn key_status(&self) -> KeyStatus {
+        self.key_provider.key_status()
+    }
+
+    pub fn health(&self) -> KeyHealth {
+        self.key_provider.health()
+    }
+}
+
 #[cfg(feature = "blocking")]
 impl<KP: KeyProvider> GenericClient<KP> {
     pub fn verify_token_with_payload<P>(&self, token_string: &str) -> Result<Token<P>, Error>
     where
         for<'a> P: Deserialize<'a>,
     {
-        let unverified_token =
-            UnverifiedToken::<P>::validate(token_string, self.check_expiration, &self.client_id)?;
-        unverified_token.verify(&self.key_provider)
+        let unverified_token = UnverifiedToken::<P>::validate(
+            token_string,
+            self.check_expiration,
+            &self.client_id,
+            &self.issuers,
+        )?;
+        unverified_token.verify(
+            self.key_provider.as_ref(),
+            self.certificate_chain_policy.as_deref(),
+        )
+    }
+
+    /// Verifies a token without deserializing its payload. Read it with
+    /// `BorrowedToken::get_payload`, into a type that borrows from the token.
+    pub fn verify_token_borrowed(&self, token_string: &str) -> Result<BorrowedToken, Error> {
+        let unverified_token = UnverifiedToken::decode(
+            token_string,
+            self.check_expiration,
+            &self.client_id,
+            &self.issuers,
+        )?;
+        unverified_token
+            .verify(
+                self.key_provider.as_ref(),
+                self.certificate_chain_policy.as_deref(),
+            )
+            .map(BorrowedToken::new)
+    }
+
+    /// Loads the keys eagerly, e.g. from a readiness probe, instead of on the first verification.
+    pub fn prefetch_keys(&self) -> Result<(), Error> {
+        self.key_provider
+            .prefetch_keys()
+            .map_err(|_| Error::RetrieveKeyFailure)
     }
 
     pub fn verify_token(&self, token_string: &str) -> Result<Token<()>, Error> {
-        self.verify_token_with_payload::<()>(token_string)
+        // `()` only deserializes from `null`, so the claims are skipped instead.
+        self.verify_token_with_payload::<IgnoredAny>(token_string)
+            .map(|token| token.map_payload(|_| ()))
     }
 
     pub fn verify_id_token(&self, token_string: &str) -> Result<Token<IdPayload>, Error> {
         self.verify_token_with_payload(token_string)
     }
+
+    /// Makes every check of `verify_token`, without stopping at the first failure, and reports
+    /// why the token would be accepted or rejected.
+    pub fn diagnose(&self, token_string: &str) -> DiagnosticReport {
+        let mut diagnosis = self.start_diagnosis(token_string);
+        if let Some(key_id) =
+            diagnosis.check_certificate_chain(self.certificate_chain_policy.as_deref())
+        {
+            diagnosis.check_key(self.key_provider.get_key(&key_id));
+        }
+        diagnosis.finish(self.key_provider.get_key_ids())
+    }
 }
 
 #[cfg(feature = "async")]
@@ -94,14 +243,50 @@ impl<KP: AsyncKeyProvider> GenericClient<KP> {
     where
         for<'a> P: Deserialize<'a>,
     {
-        let unverified_token =
-            UnverifiedToken::<P>::validate(token_string, self.check_expiration, &self.client_id)?;
-        unverified_token.verify_async(&self.key_provider).await
+        let unverified_token = UnverifiedToken::<P>::validate(
+            token_string,
+            self.check_expiration,
+            &self.client_id,
+            &self.issuers,
+        )?;
+        unverified_token
+            .verify_async(
+                self.key_provider.as_ref(),
+                self.certificate_chain_policy.as_deref(),
+            )
+            .await
+    }
+
+    pub async fn verify_token_borrowed_async(
+        &self,
+        token_string: &str,
+    ) -> Result<BorrowedToken, Error> {
+        let unverified_token = UnverifiedToken::decode(
+            token_string,
+            self.check_expiration,
+            &self.client_id,
+            &self.issuers,
+        )?;
+        unverified_token
+            .verify_async(
+                self.key_provider.as_ref(),
+                self.certificate_chain_policy.as_deref(),
+            )
+            .await
+            .map(BorrowedToken::new)
+    }
+
+    pub async fn prefetch_keys_async(&self) -> Result<(), Error> {
+        self.key_provider
+            .prefetch_keys_async()
+            .await
+            .map_err(|_| Error::RetrieveKeyFailure)
     }
 
     pub async fn verify_token_async(&self, token_string: &str) -> Result<Token<()>, Error> {
-        self.verify_token_with_payload_async::<()>(token_string)
+        self.verify_token_with_payload_async::<IgnoredAny>(token_string)
             .await
+            .map(|token| token.map_payload(|_| ()))
     }
 
     pub async fn verify_id_token_async(
@@ -110,4 +295,14 @@ impl<KP: AsyncKeyProvider> GenericClient<KP> {
     ) -> Result<Token<IdPayload>, Error> {
         self.verify_token_with_payload_async(token_string).await
     }
+
+    pub async fn diagnose_async(&self, token_string: &str) -> DiagnosticReport {
+        let mut diagnosis = self.start_diagnosis(token_string);
+        if let Some(key_id) =
+            diagnosis.check_certificate_chain(self.certificate_chain_policy.as_deref())
+        {
+            diagnosis.check_key(self.key_provider.get_key_async(&key_id).await);
+        }
+        diagnosis.finish(self.key_provider.get_key_ids_async().await)
+    }
 }
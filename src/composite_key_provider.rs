@@ -0,0 +1,134 @@
+use crate::jwk::JsonWebKey;
+#[cfg(feature = "async")]
+use crate::key_provider::AsyncKeyProvider;
+#[cfg(feature = "blocking")]
+use crate::key_provider::KeyProvider;
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+/// A provider that can be chained in a `CompositeKeyProvider`, i.e. one that implements every
+/// key provider trait enabled by the crate features.
+#[cfg(all(feature = "blocking", feature = "async"))]
+pub trait SourceKeyProvider: KeyProvider + AsyncKeyProvider {}
+#[cfg(all(feature = "blocking", feature = "async"))]
+impl<T: KeyProvider + AsyncKeyProvider> SourceKeyProvider for T {}
+
+#[cfg(all(feature = "blocking", not(feature = "async")))]
+pub trait SourceKeyProvider: KeyProvider {}
+#[cfg(all(feature = "blocking", not(feature = "async")))]
+impl<T: KeyProvider> SourceKeyProvider for T {}
+
+#[cfg(all(not(feature = "blocking"), feature = "async"))]
+pub trait SourceKeyProvider: AsyncKeyProvider {}
+#[cfg(all(not(feature = "blocking"), feature = "async"))]
+impl<T: AsyncKeyProvider> SourceKeyProvider for T {}
+
+#[cfg(not(any(feature = "blocking", feature = "async")))]
+pub trait SourceKeyProvider: Send + Sync {}
+#[cfg(not(any(feature = "blocking", feature = "async")))]
+impl<T: Send + Sync> SourceKeyProvider for T {}
+
+struct Source {
+    provider: Box<dyn SourceKeyProvider>,
+    issuers: Option<Vec<String>>,
+}
+
+impl Source {
+    fn bind(&self, mut key: JsonWebKey) -> JsonWebKey {
+        if let Some(ref issuers) = self.issuers {
+            key.bind_issuers(issuers);
+        }
+        key
+    }
+}
+
+/// Looks keys up in several providers, in the order they were added, and returns the first
+/// match. A failing provider only fails the lookup if no later provider has the key.
+#[derive(Default)]
+pub struct CompositeKeyProvider {
+    sources: Vec<Source>,
+}
+
+impl CompositeKeyProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_provider<T: SourceKeyProvider + 'static>(mut self, provider: T) -> Self {
+        self.sources.push(Source {
+            provider: Box::new(provider),
+            issuers: None,
+        });
+        self
+    }
+
+    /// Adds a provider whose keys only verify tokens from the given issuers.
+    pub fn with_bound_provider<T: SourceKeyProvider + 'static>(
+        mut self,
+        provider: T,
+        issuers: &[&str],
+    ) -> Self {
+        self.sources.push(Source {
+            provider: Box::new(provider),
+            issuers: Some(issuers.iter().map(|&issuer| issuer.to_owned()).collect()),
+        });
+        self
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl KeyProvider for CompositeKeyProvider {
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        let mut result = Ok(None);
+        for source in &self.sources {
+            match source.provider.get_key(key_id) {
+                Ok(Some(key)) => return Ok(Some(source.bind(key))),
+                Ok(None) => {}
+                Err(()) => result = Err(()),
+            }
+        }
+        result
+    }
+    fn prefetch_keys(&self) -> Result<(), ()> {
+        for source in &self.sources {
+            source.provider.prefetch_keys()?;
+        }
+        Ok(())
+    }
+    fn get_key_ids(&self) -> Vec<String> {
+        let mut key_ids = Vec::new();
+        for source in &self.sources {
+            key_ids.extend(source.provider.get_key_ids());
+        }
+        key_ids
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncKeyProvider for CompositeKeyProvider {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        let mut result = Ok(None);
+        for source in &self.sources {
+            match source.provider.get_key_async(key_id).await {
+                Ok(Some(key)) => return Ok(Some(source.bind(key))),
+                Ok(None) => {}
+                Err(()) => result = Err(()),
+            }
+        }
+        result
+    }
+    async fn prefetch_keys_async(&self) -> Result<(), ()> {
+        for source in &self.sources {
+            source.provider.prefetch_keys_async().await?;
+        }
+        Ok(())
+    }
+    async fn get_key_ids_async(&self) -> Vec<String> {
+        let mut key_ids = Vec::new();
+        for source in &self.sources {
+            key_ids.extend(source.provider.get_key_ids_async().await);
+        }
+        key_ids
+    }
+}
@@ -0,0 +1,365 @@
+use crate::base64_decode;
+use crate::certificate_chain::CertificateChainPolicy;
+use crate::error::Error;
+use crate::header::Header;
+use crate::jwk::JsonWebKey;
+use crate::token::RequiredClaims;
+use crate::unverified_token::verify_signature;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The checks a client makes before accepting a token, in the order they are made.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Check {
+    /// The token has a header, payload and signature segment.
+    Format,
+    Header,
+    /// The payload is JSON with every claim in `RequiredClaims`.
+    Claims,
+    SignatureEncoding,
+    Audience,
+    Issuer,
+    Expiration,
+    /// The token was not issued after it expires.
+    IssuedAt,
+    /// The key was found in the key provider, or taken from a trusted `x5c` chain.
+    KeyLookup,
+    /// The key's source is allowed to sign tokens from the token's issuer.
+    IssuerBinding,
+    Signature,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CheckOutcome {
+    Passed,
+    Failed,
+    /// The check could not be made because an earlier one failed, or it is disabled.
+    Skipped,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: CheckOutcome,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Why a token was accepted or rejected, as returned by `GenericClient::diagnose`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DiagnosticReport {
+    /// The clock time the expiration was checked against.
+    pub checked_at: SystemTime,
+    pub checks: Vec<CheckResult>,
+    /// The key ids the key provider held after the lookup.
+    pub available_key_ids: Vec<String>,
+}
+
+impl DiagnosticReport {
+    /// Whether the token would be accepted, i.e. no check failed.
+    pub fn is_valid(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|result| result.outcome == CheckOutcome::Failed)
+    }
+
+    pub fn get_check(&self, check: Check) -> Option<&CheckResult> {
+        self.checks.iter().find(|result| result.check == check)
+    }
+}
+
+impl fmt::Display for DiagnosticReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "checked at {}", timestamp(self.checked_at))?;
+        for result in &self.checks {
+            writeln!(
+                f,
+                "{:?} {:?}: expected {}, got {}",
+                result.check, result.outcome, result.expected, result.actual
+            )?;
+        }
+        write!(f, "available keys: [{}]", self.available_key_ids.join(", "))
+    }
+}
+
+/// Makes the checks of `UnverifiedToken::decode` and `verify_with_key`, recording every result
+/// instead of stopping at the first failure.
+pub(crate) struct Diagnosis {
+    checked_at: SystemTime,
+    checks: Vec<CheckResult>,
+    header: Option<Header>,
+    signed_body: String,
+    signature: Option<Vec<u8>>,
+    claims: Option<RequiredClaims>,
+}
+
+impl Diagnosis {
+    pub fn new(
+        token_string: &str,
+        check_expiration: bool,
+        client_id: &str,
+        issuers: &[String],
+    ) -> Self {
+        let checked_at = SystemTime::now();
+        let segments: Vec<&str> = token_string.split('.').collect();
+        let mut diagnosis = Self {
+            checked_at,
+            checks: Vec::new(),
+            header: None,
+            signed_body: segments
+                .iter()
+                .take(2)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("."),
+            signature: None,
+            claims: None,
+        };
+        diagnosis.record(
+            Check::Format,
+            segments.len() >= 3,
+            "header.payload.signature",
+            format!("{} segment(s)", segments.len()),
+        );
+
+        match segments
+            .first()
+            .map(|encoded| decode_json::<Header>(encoded))
+        {
+            Some(Ok(header)) => {
+                diagnosis.pass(Check::Header, "a JSON header", describe_header(&header));
+                diagnosis.header = Some(header);
+            }
+            Some(Err(reason)) => diagnosis.fail(Check::Header, "a JSON header", reason),
+            None => diagnosis.skip(Check::Header, "a JSON header"),
+        }
+        match segments
+            .get(1)
+            .map(|encoded| decode_json::<RequiredClaims>(encoded))
+        {
+            Some(Ok(claims)) => {
+                diagnosis.pass(Check::Claims, "the required claims", "present");
+                diagnosis.claims = Some(claims);
+            }
+            Some(Err(reason)) => diagnosis.fail(Check::Claims, "the required claims", reason),
+            None => diagnosis.skip(Check::Claims, "the required claims"),
+        }
+        match segments.get(2).map(|encoded| base64_decode(encoded)) {
+            Some(Ok(signature)) => {
+                let actual = format!("{} bytes", signature.len());
+                diagnosis.pass(Check::SignatureEncoding, "base64url", actual);
+                diagnosis.signature = Some(signature);
+            }
+            Some(Err(_)) => diagnosis.fail(Check::SignatureEncoding, "base64url", "invalid base64"),
+            None => diagnosis.skip(Check::SignatureEncoding, "base64url"),
+        }
+
+        let now = timestamp(checked_at);
+        match diagnosis.claims.clone() {
+            Some(claims) => {
+                diagnosis.record(
+                    Check::Audience,
+                    claims.get_audience() == client_id,
+                    client_id,
+                    claims.get_audience(),
+                );
+                diagnosis.record(
+                    Check::Issuer,
+                    issuers.contains(&claims.get_issuer()),
+                    format!("one of [{}]", issuers.join(", ")),
+                    claims.get_issuer(),
+                );
+                let expected_expiration = format!("exp >= {}", now);
+                if check_expiration {
+                    diagnosis.record(
+                        Check::Expiration,
+                        claims.get_expires_at() >= now,
+                        expected_expiration,
+                        claims.get_expires_at().to_string(),
+                    );
+                } else {
+                    diagnosis.skip(Check::Expiration, expected_expiration);
+                }
+                diagnosis.record(
+                    Check::IssuedAt,
+                    claims.get_issued_at() <= claims.get_expires_at(),
+                    format!("iat <= {}", claims.get_expires_at()),
+                    claims.get_issued_at().to_string(),
+                );
+            }
+            None => {
+                diagnosis.skip(Check::Audience, client_id);
+                diagnosis.skip(Check::Issuer, format!("one of [{}]", issuers.join(", ")));
+                diagnosis.skip(Check::Expiration, format!("exp >= {}", now));
+                diagnosis.skip(Check::IssuedAt, "iat <= exp");
+            }
+        }
+        diagnosis
+    }
+
+    /// The id of the key to look up in the key provider, or `None` if there is nothing to look
+    /// up: the header has no key id or the key came from the token's `x5c` chain.
+    pub fn check_certificate_chain(
+        &mut self,
+        chain_policy: Option<&CertificateChainPolicy>,
+    ) -> Option<String> {
+        let header = match self.header.clone() {
+            Some(header) => header,
+            None => {
+                self.skip(Check::KeyLookup, "a key id in the header");
+                self.skip_signing();
+                return None;
+            }
+        };
+        match (header.certificate_chain, chain_policy) {
+            (Some(chain), Some(chain_policy)) => {
+                let key_id = header.key_id.unwrap_or_default();
+                let key = chain_policy.leaf_key(&key_id, &chain);
+                let actual = match key {
+                    Ok(_) => "trusted",
+                    Err(_) => "not trusted",
+                };
+                self.check_key_from("a trusted x5c certificate chain", key.ok(), actual);
+                None
+            }
+            _ if header.key_id.is_none() => {
+                self.check_key_from("a key id in the header", None, "no kid");
+                None
+            }
+            _ => header.key_id,
+        }
+    }
+
+    /// Checks the key found by the key provider, then the signature made with it.
+    pub fn check_key(&mut self, key: Result<Option<JsonWebKey>, ()>) {
+        let expected = match self
+            .header
+            .as_ref()
+            .and_then(|header| header.key_id.as_ref())
+        {
+            Some(key_id) => format!("key {}", key_id),
+            None => "a key id in the header".to_owned(),
+        };
+        match key {
+            Ok(Some(key)) => self.check_key_from(expected, Some(key), "found"),
+            Ok(None) => self.check_key_from(expected, None, "not found"),
+            Err(()) => self.check_key_from(expected, None, "the key could not be retrieved"),
+        }
+    }
+
+    fn check_key_from<E: Into<String>>(
+        &mut self,
+        expected: E,
+        key: Option<JsonWebKey>,
+        actual: &str,
+    ) {
+        let key = match key {
+            Some(key) => {
+                self.pass(Check::KeyLookup, expected, actual);
+                key
+            }
+            None => {
+                self.fail(Check::KeyLookup, expected, actual);
+                return self.skip_signing();
+            }
+        };
+        let header = self.header.clone().unwrap_or_default();
+
+        match self.claims.as_ref().map(|claims| claims.get_issuer()) {
+            Some(issuer) => {
+                let accepted = key.accepts_issuer(&issuer);
+                let actual = if accepted { "allowed" } else { "not allowed" };
+                self.record(Check::IssuerBinding, accepted, issuer, actual);
+            }
+            None => self.skip(Check::IssuerBinding, "the token's issuer"),
+        }
+
+        let expected = match header.algorithm.or_else(|| key.get_algorithm()) {
+            Some(algorithm) => format!("a valid {:?} signature", algorithm),
+            None => "a valid signature".to_owned(),
+        };
+        match self.signature.as_ref() {
+            Some(signature) => {
+                match verify_signature(&key, &header, &self.signed_body, signature) {
+                    Ok(()) => self.pass(Check::Signature, expected, "valid"),
+                    Err(Error::UnsupportedAlgorithm(algorithm)) => self.fail(
+                        Check::Signature,
+                        expected,
+                        format!("{:?} is not supported", algorithm),
+                    ),
+                    Err(_) => self.fail(Check::Signature, expected, "invalid"),
+                }
+            }
+            None => self.skip(Check::Signature, expected),
+        }
+    }
+
+    pub fn finish(self, available_key_ids: Vec<String>) -> DiagnosticReport {
+        DiagnosticReport {
+            checked_at: self.checked_at,
+            checks: self.checks,
+            available_key_ids,
+        }
+    }
+
+    fn skip_signing(&mut self) {
+        self.skip(Check::IssuerBinding, "the token's issuer");
+        self.skip(Check::Signature, "a valid signature");
+    }
+
+    fn record<E: Into<String>, A: Into<String>>(
+        &mut self,
+        check: Check,
+        passed: bool,
+        expected: E,
+        actual: A,
+    ) {
+        let outcome = if passed {
+            CheckOutcome::Passed
+        } else {
+            CheckOutcome::Failed
+        };
+        self.checks.push(CheckResult {
+            check,
+            outcome,
+            expected: expected.into(),
+            actual: actual.into(),
+        });
+    }
+
+    fn pass<E: Into<String>, A: Into<String>>(&mut self, check: Check, expected: E, actual: A) {
+        self.record(check, true, expected, actual);
+    }
+
+    fn fail<E: Into<String>, A: Into<String>>(&mut self, check: Check, expected: E, actual: A) {
+        self.record(check, false, expected, actual);
+    }
+
+    fn skip<E: Into<String>>(&mut self, check: Check, expected: E) {
+        self.checks.push(CheckResult {
+            check,
+            outcome: CheckOutcome::Skipped,
+            expected: expected.into(),
+            actual: "not checked".to_owned(),
+        });
+    }
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(encoded: &str) -> Result<T, String> {
+    let decoded = base64_decode(encoded).map_err(|_| "invalid base64".to_owned())?;
+    serde_json::from_slice(&decoded).map_err(|error| error.to_string())
+}
+
+fn describe_header(header: &Header) -> String {
+    serde_json::to_string(header).unwrap_or_default()
+}
+
+fn timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
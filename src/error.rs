@@ -7,6 +7,8 @@ pub enum Error {
     RetrieveKeyFailure,
     UnsupportedAlgorithm(Algorithm),
     Expired,
+    /// A certificate or hostname given to configure the client was rejected.
+    InvalidCertificate,
 }
 
 impl From<DecodeError> for Error {
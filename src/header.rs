@@ -1,7 +1,12 @@
+use crate::algorithm::Algorithm;
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Eq, PartialEq, Debug)]
 pub struct Header {
-    #[serde(rename = "kid")]
-    pub key_id: String,
+    #[serde(rename = "kid", default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(rename = "alg", default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<Algorithm>,
+    #[serde(rename = "x5c", default, skip_serializing_if = "Option::is_none")]
+    pub certificate_chain: Option<Vec<String>>,
 }
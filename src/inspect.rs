@@ -0,0 +1,53 @@
+use crate::base64_decode;
+use crate::error::Error;
+use crate::header::Header;
+use crate::token::RequiredClaims;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// What a token says about itself, as returned by `decode_unverified`. Nothing in it has been
+/// checked: anyone can create a token with these contents.
+#[derive(Clone, Debug)]
+pub struct UnverifiedContents {
+    header: serde_json::Value,
+    claims: serde_json::Value,
+    signature: Vec<u8>,
+}
+
+/// Decodes a token WITHOUT verifying its signature or claims, to see what is in a token that
+/// was rejected. Never make decisions based on the result; use a `Client` for that.
+pub fn decode_unverified(token_string: &str) -> Result<UnverifiedContents, Error> {
+    let mut segments = token_string.split('.');
+    let encoded_header = segments.next().ok_or(Error::InvalidToken)?;
+    let encoded_payload = segments.next().ok_or(Error::InvalidToken)?;
+    let signature = match segments.next() {
+        Some(encoded_signature) => base64_decode(encoded_signature)?,
+        None => Vec::new(),
+    };
+    Ok(UnverifiedContents {
+        header: serde_json::from_slice(&base64_decode(encoded_header)?)?,
+        claims: serde_json::from_slice(&base64_decode(encoded_payload)?)?,
+        signature,
+    })
+}
+
+impl UnverifiedContents {
+    pub fn get_header_json(&self) -> &serde_json::Value {
+        &self.header
+    }
+    pub fn get_claims_json(&self) -> &serde_json::Value {
+        &self.claims
+    }
+    pub fn get_signature(&self) -> &[u8] {
+        &self.signature
+    }
+    pub fn get_header(&self) -> Result<Header, Error> {
+        Ok(Header::deserialize(&self.header)?)
+    }
+    pub fn get_required_claims(&self) -> Result<RequiredClaims, Error> {
+        Ok(RequiredClaims::deserialize(&self.claims)?)
+    }
+    pub fn get_payload<P: DeserializeOwned>(&self) -> Result<P, Error> {
+        Ok(P::deserialize(&self.claims)?)
+    }
+}
@@ -1,51 +1,440 @@
 use crate::algorithm::Algorithm;
-use crate::base64_decode;
 use crate::error::Error;
-use openssl::bn::BigNum;
-use openssl::hash::MessageDigest;
-use openssl::pkey::PKey;
+use crate::key_policy::KeyPolicy;
+use crate::{base64_decode, base64_encode};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
 use openssl::rsa::Rsa;
 use openssl::sign::Verifier;
-use serde_derive::Deserialize;
+use openssl::x509::X509;
+use serde::{Deserialize, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use std::time::SystemTime;
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Clone)]
 pub struct JsonWebKeySet {
     keys: Vec<JsonWebKey>,
 }
 
+impl<'de> Deserialize<'de> for JsonWebKeySet {
+    /// Keys that cannot be parsed, or cannot be used to check signatures, are left out, as
+    /// RFC 7517 section 5 recommends.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct RawKeySet {
+            keys: Vec<serde_json::Value>,
+        }
+        let raw = RawKeySet::deserialize(deserializer)?;
+        let keys = raw
+            .keys
+            .into_iter()
+            .filter_map(|key| serde_json::from_value::<JsonWebKey>(key).ok())
+            .filter(|key| key.check_signing_key().is_ok())
+            .collect();
+        Ok(Self::new(keys))
+    }
+}
+
 impl JsonWebKeySet {
+    pub fn new(keys: Vec<JsonWebKey>) -> Self {
+        Self { keys }
+    }
+
+    pub fn get_keys(&self) -> &[JsonWebKey] {
+        &self.keys
+    }
+
+    pub fn get_key_ids(&self) -> Vec<String> {
+        self.keys.iter().map(|key| key.id.clone()).collect()
+    }
+
+    /// The key with id `id`, unless its certificate has expired.
     pub fn get_key(&self, id: &str) -> Option<JsonWebKey> {
-        self.keys.iter().find(|key| key.id == id).cloned()
+        self.keys
+            .iter()
+            .find(|key| key.id == id && !key.has_expired())
+            .cloned()
+    }
+
+    /// Leaves out the keys that do not meet `policy`, and returns their ids.
+    pub fn apply_policy(&mut self, policy: &KeyPolicy) -> Vec<String> {
+        let (accepted, rejected) = self.keys.drain(..).partition(|key| policy.accepts(key));
+        self.keys = accepted;
+        rejected.into_iter().map(|key: JsonWebKey| key.id).collect()
+    }
+
+    /// Parses the public keys up front, so they are not rebuilt for every verification.
+    /// Keys that fail to parse are left as they are, and fail when used.
+    pub fn prepare_keys(&mut self) {
+        for key in &mut self.keys {
+            let _ = key.prepare();
+        }
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KeyType {
+    #[serde(rename = "RSA")]
+    Rsa,
+    #[serde(rename = "EC")]
+    Ec,
+    #[serde(rename = "oct")]
+    Symmetric,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KeyUse {
+    #[serde(rename = "sig")]
+    Signature,
+    #[serde(rename = "enc")]
+    Encryption,
+}
+
+/// A JSON Web Key as defined in RFC 7517, for RSA and elliptic curve public keys.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct JsonWebKey {
-    #[serde(rename = "alg")]
-    algorithm: Algorithm,
+    #[serde(rename = "kty")]
+    key_type: KeyType,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    key_use: Option<KeyUse>,
+    #[serde(rename = "key_ops", default, skip_serializing_if = "Option::is_none")]
+    key_operations: Option<Vec<String>>,
+    #[serde(rename = "alg", default, skip_serializing_if = "Option::is_none")]
+    algorithm: Option<Algorithm>,
     #[serde(rename = "kid")]
     id: String,
-    n: String,
-    e: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+    #[serde(rename = "x5c", default, skip_serializing_if = "Option::is_none")]
+    certificate_chain: Option<Vec<String>>,
+    #[serde(rename = "x5t", default, skip_serializing_if = "Option::is_none")]
+    sha1_thumbprint: Option<String>,
+    #[serde(rename = "x5t#S256", default, skip_serializing_if = "Option::is_none")]
+    sha256_thumbprint: Option<String>,
+    #[serde(skip)]
+    public_key: Option<PKey<Public>>,
+    #[serde(skip)]
+    issuers: Option<Vec<String>>,
+    /// When the certificate the key was taken from expires.
+    #[serde(skip)]
+    valid_until: Option<SystemTime>,
 }
 
 impl JsonWebKey {
+    /// Builds an RS256 key from the RSA public key of a certificate.
+    pub fn from_rsa_public_key(id: &str, public_key: PKey<Public>) -> Result<Self, Error> {
+        public_key.rsa()?;
+        let mut key = Self::from_public_key(id, public_key)?;
+        key.algorithm = Some(Algorithm::RS256);
+        Ok(key)
+    }
+
+    /// Builds a key without `alg` from an RSA or elliptic curve public key.
+    pub fn from_public_key(id: &str, public_key: PKey<Public>) -> Result<Self, Error> {
+        let mut key = Self {
+            key_type: KeyType::Rsa,
+            key_use: None,
+            key_operations: None,
+            algorithm: None,
+            id: id.to_owned(),
+            n: None,
+            e: None,
+            crv: None,
+            x: None,
+            y: None,
+            certificate_chain: None,
+            sha1_thumbprint: None,
+            sha256_thumbprint: None,
+            public_key: None,
+            issuers: None,
+            valid_until: None,
+        };
+        match public_key.id() {
+            Id::RSA => {
+                let rsa = public_key.rsa()?;
+                key.n = Some(base64_encode(&rsa.n().to_vec()));
+                key.e = Some(base64_encode(&rsa.e().to_vec()));
+            }
+            Id::EC => {
+                let ec = public_key.ec_key()?;
+                let (curve, length) = match ec.group().curve_name() {
+                    Some(Nid::X9_62_PRIME256V1) => ("P-256", 32),
+                    Some(Nid::SECP384R1) => ("P-384", 48),
+                    Some(Nid::SECP521R1) => ("P-521", 66),
+                    _ => return Err(Error::InvalidToken),
+                };
+                let (mut x, mut y) = (BigNum::new()?, BigNum::new()?);
+                let mut context = BigNumContext::new()?;
+                ec.public_key()
+                    .affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut context)?;
+                key.key_type = KeyType::Ec;
+                key.crv = Some(curve.to_owned());
+                key.x = Some(base64_encode(&x.to_vec_padded(length)?));
+                key.y = Some(base64_encode(&y.to_vec_padded(length)?));
+            }
+            _ => return Err(Error::InvalidToken),
+        }
+        key.public_key = Some(public_key);
+        Ok(key)
+    }
+
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
 
-    pub fn verify(&self, body: &[u8], signature: &[u8]) -> Result<(), Error> {
-        match self.algorithm {
-            Algorithm::RS256 => {
-                let n = BigNum::from_slice(&base64_decode(&self.n)?)?;
-                let e = BigNum::from_slice(&base64_decode(&self.e)?)?;
-                let key = PKey::from_rsa(Rsa::from_public_components(n, e)?)?;
-                let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
-                verifier.update(body)?;
-                verifier.verify(signature)?;
-                Ok(())
+    pub fn get_key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    pub fn get_algorithm(&self) -> Option<Algorithm> {
+        self.algorithm
+    }
+
+    pub fn get_curve(&self) -> Option<&str> {
+        self.crv.as_deref()
+    }
+
+    /// Restricts the key to verifying tokens from the given issuers. A key that is already bound
+    /// keeps only the issuers in both lists, so a binding can never be widened.
+    pub fn bind_issuers(&mut self, issuers: &[String]) {
+        self.issuers = Some(match self.issuers.take() {
+            Some(bound) => bound
+                .into_iter()
+                .filter(|issuer| issuers.contains(issuer))
+                .collect(),
+            None => issuers.to_vec(),
+        });
+    }
+
+    pub(crate) fn with_valid_until(mut self, valid_until: SystemTime) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    /// Whether the certificate the key was taken from has expired. Key sets can outlive their
+    /// certificates in a cache.
+    pub fn has_expired(&self) -> bool {
+        self.valid_until
+            .is_some_and(|valid_until| valid_until < SystemTime::now())
+    }
+
+    pub fn accepts_issuer(&self, issuer: &str) -> bool {
+        match self.issuers {
+            Some(ref issuers) => issuers.iter().any(|bound| bound == issuer),
+            None => true,
+        }
+    }
+
+    pub fn prepare(&mut self) -> Result<(), Error> {
+        if self.public_key.is_none() {
+            self.public_key = Some(self.build_public_key()?);
+        }
+        Ok(())
+    }
+
+    fn build_public_key(&self) -> Result<PKey<Public>, Error> {
+        match self.key_type {
+            KeyType::Rsa => {
+                let n = BigNum::from_slice(&base64_decode(required(&self.n)?)?)?;
+                let e = BigNum::from_slice(&base64_decode(required(&self.e)?)?)?;
+                Ok(PKey::from_rsa(Rsa::from_public_components(n, e)?)?)
+            }
+            KeyType::Ec => {
+                let group = EcGroup::from_curve_name(curve_nid(required(&self.crv)?)?)?;
+                let x = BigNum::from_slice(&base64_decode(required(&self.x)?)?)?;
+                let y = BigNum::from_slice(&base64_decode(required(&self.y)?)?)?;
+                let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+                Ok(PKey::from_ec_key(key)?)
             }
-            _ => Err(Error::UnsupportedAlgorithm(self.algorithm)),
+            KeyType::Symmetric => Err(Error::InvalidToken),
         }
     }
+
+    pub fn public_key(&self) -> Result<PKey<Public>, Error> {
+        match self.public_key {
+            Some(ref key) => Ok(key.clone()),
+            None => self.build_public_key(),
+        }
+    }
+
+    /// The public key as a DER encoded SubjectPublicKeyInfo.
+    pub fn to_der(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.public_key()?.public_key_to_der()?)
+    }
+
+    /// The public key as a PEM encoded SubjectPublicKeyInfo (`BEGIN PUBLIC KEY`).
+    pub fn to_pem(&self) -> Result<String, Error> {
+        let pem = self.public_key()?.public_key_to_pem()?;
+        String::from_utf8(pem).map_err(|_| Error::InvalidToken)
+    }
+
+    /// An RSA public key in the PKCS#1 PEM format (`BEGIN RSA PUBLIC KEY`).
+    pub fn to_pkcs1_pem(&self) -> Result<String, Error> {
+        let pem = self.public_key()?.rsa()?.public_key_to_pem_pkcs1()?;
+        String::from_utf8(pem).map_err(|_| Error::InvalidToken)
+    }
+
+    /// The JWK thumbprint from RFC 7638, base64url encoded. Use `MessageDigest::sha256()` to
+    /// compare with other implementations.
+    pub fn thumbprint(&self, digest: MessageDigest) -> Result<String, Error> {
+        // The required members in lexicographic order, without whitespace.
+        let members = match self.key_type {
+            KeyType::Rsa => format!(
+                r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+                serde_json::to_string(required(&self.e)?)?,
+                serde_json::to_string(required(&self.n)?)?
+            ),
+            KeyType::Ec => format!(
+                r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+                serde_json::to_string(required(&self.crv)?)?,
+                serde_json::to_string(required(&self.x)?)?,
+                serde_json::to_string(required(&self.y)?)?
+            ),
+            KeyType::Symmetric => return Err(Error::InvalidToken),
+        };
+        Ok(base64_encode(&hash(digest, members.as_bytes())?))
+    }
+
+    /// Rejects keys that are not fit for signature checks, including keys whose `x5c` certificate
+    /// does not hold the same public key.
+    fn check_signing_key(&self) -> Result<(), Error> {
+        self.check_usage()?;
+        if self.certificate_chain.is_some() {
+            self.check_certificate()?;
+        }
+        Ok(())
+    }
+
+    /// Rejects keys meant for encryption, and keys whose `alg` does not match their `kty`.
+    fn check_usage(&self) -> Result<(), Error> {
+        if self.key_use == Some(KeyUse::Encryption) {
+            return Err(Error::InvalidToken);
+        }
+        if let Some(ref operations) = self.key_operations {
+            if !operations.iter().any(|operation| operation == "verify") {
+                return Err(Error::InvalidToken);
+            }
+        }
+        if let Some(algorithm) = self.algorithm {
+            self.check_algorithm(algorithm)?;
+        }
+        Ok(())
+    }
+
+    fn check_algorithm(&self, algorithm: Algorithm) -> Result<(), Error> {
+        let matches = match (self.key_type, algorithm) {
+            (KeyType::Rsa, Algorithm::RS256)
+            | (KeyType::Rsa, Algorithm::RS384)
+            | (KeyType::Rsa, Algorithm::RS512) => true,
+            (KeyType::Ec, Algorithm::ES256) => self.crv.as_deref() == Some("P-256"),
+            (KeyType::Ec, Algorithm::ES384) => self.crv.as_deref() == Some("P-384"),
+            (KeyType::Ec, Algorithm::ES512) => self.crv.as_deref() == Some("P-521"),
+            (KeyType::Symmetric, Algorithm::HS256)
+            | (KeyType::Symmetric, Algorithm::HS384)
+            | (KeyType::Symmetric, Algorithm::HS512) => true,
+            _ => false,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::InvalidToken)
+        }
+    }
+
+    fn check_certificate(&self) -> Result<(), Error> {
+        let chain = required(&self.certificate_chain)?;
+        let der = base64::decode(chain.first().ok_or(Error::InvalidToken)?)?;
+        if let Some(ref thumbprint) = self.sha1_thumbprint {
+            if base64_decode(thumbprint)? != hash(MessageDigest::sha1(), &der)?.to_vec() {
+                return Err(Error::InvalidToken);
+            }
+        }
+        if let Some(ref thumbprint) = self.sha256_thumbprint {
+            if base64_decode(thumbprint)? != hash(MessageDigest::sha256(), &der)?.to_vec() {
+                return Err(Error::InvalidToken);
+            }
+        }
+        let certificate_key = X509::from_der(&der)?.public_key()?;
+        if !certificate_key.public_eq(&*self.build_public_key()?) {
+            return Err(Error::InvalidToken);
+        }
+        Ok(())
+    }
+
+    /// Verifies with the key's own `alg`. Keys without one can only be used through
+    /// `verify_with_algorithm`.
+    pub fn verify(&self, body: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let algorithm = self.algorithm.ok_or(Error::InvalidToken)?;
+        self.verify_with_algorithm(algorithm, body, signature)
+    }
+
+    /// Verifies with the algorithm named in a token header, which has to be the key's `alg` if
+    /// it has one, and has to suit the key type otherwise.
+    pub fn verify_with_algorithm(
+        &self,
+        algorithm: Algorithm,
+        body: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        if self.algorithm.is_some() && self.algorithm != Some(algorithm) {
+            return Err(Error::InvalidToken);
+        }
+        self.check_usage()?;
+        self.check_algorithm(algorithm)?;
+        let (digest, coordinate_length) = match algorithm {
+            Algorithm::RS256 => (MessageDigest::sha256(), None),
+            Algorithm::RS384 => (MessageDigest::sha384(), None),
+            Algorithm::RS512 => (MessageDigest::sha512(), None),
+            Algorithm::ES256 => (MessageDigest::sha256(), Some(32)),
+            Algorithm::ES384 => (MessageDigest::sha384(), Some(48)),
+            Algorithm::ES512 => (MessageDigest::sha512(), Some(66)),
+            _ => return Err(Error::UnsupportedAlgorithm(algorithm)),
+        };
+        let key = self.public_key()?;
+        let mut verifier = Verifier::new(digest, &key)?;
+        verifier.update(body)?;
+        let valid = match coordinate_length {
+            Some(length) => verifier.verify(&ecdsa_signature_to_der(signature, length)?)?,
+            None => verifier.verify(signature)?,
+        };
+        if !valid {
+            return Err(Error::InvalidToken);
+        }
+        Ok(())
+    }
+}
+
+fn required<T>(parameter: &Option<T>) -> Result<&T, Error> {
+    parameter.as_ref().ok_or(Error::InvalidToken)
+}
+
+fn curve_nid(curve: &str) -> Result<Nid, Error> {
+    match curve {
+        "P-256" => Ok(Nid::X9_62_PRIME256V1),
+        "P-384" => Ok(Nid::SECP384R1),
+        "P-521" => Ok(Nid::SECP521R1),
+        _ => Err(Error::InvalidToken),
+    }
+}
+
+/// JWS signs with the two ECDSA integers concatenated, OpenSSL expects them DER encoded.
+fn ecdsa_signature_to_der(signature: &[u8], coordinate_length: usize) -> Result<Vec<u8>, Error> {
+    if signature.len() != 2 * coordinate_length {
+        return Err(Error::InvalidToken);
+    }
+    let r = BigNum::from_slice(&signature[..coordinate_length])?;
+    let s = BigNum::from_slice(&signature[coordinate_length..])?;
+    Ok(EcdsaSig::from_private_components(r, s)?.to_der()?)
 }
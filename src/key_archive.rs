@@ -0,0 +1,327 @@
+use crate::jwk::JsonWebKey;
+use crate::key_cache_store::write_atomically;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A key as it was observed by a key provider.
+#[derive(Clone)]
+pub struct ArchivedKey {
+    pub key: JsonWebKey,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// When the newest key set that contained the key expired.
+    pub valid_until: SystemTime,
+}
+
+impl ArchivedKey {
+    /// Compares whole seconds, which is all `FileKeyArchive` keeps.
+    fn is_same_as(&self, other: &ArchivedKey) -> bool {
+        let times = |key: &ArchivedKey| {
+            [key.first_seen, key.last_seen, key.valid_until].map(|time| to_timestamp(time).ok())
+        };
+        times(self) == times(other)
+            && serde_json::to_value(&self.key).ok() == serde_json::to_value(&other.key).ok()
+    }
+
+    /// Whether the key was published at `time`. Tokens issued before the archive first saw the
+    /// key are not accepted, so start archiving before you need to verify old tokens.
+    pub fn was_current_at(&self, time: SystemTime) -> bool {
+        self.first_seen <= time && time <= self.valid_until
+    }
+}
+
+/// Keeps every key a provider has seen, so old tokens can be verified after the key has been
+/// rotated out. Entries are keyed by key id.
+pub trait KeyArchive: Send + Sync {
+    fn get(&self, key_id: &str) -> Option<ArchivedKey>;
+    fn put(&self, key: &ArchivedKey);
+
+    /// Stores the keys of a key set. Archives that write every change out should override this
+    /// to write once.
+    fn put_all(&self, keys: &[ArchivedKey]) {
+        for key in keys {
+            self.put(key);
+        }
+    }
+}
+
+impl<A: KeyArchive + ?Sized> KeyArchive for Arc<A> {
+    fn get(&self, key_id: &str) -> Option<ArchivedKey> {
+        (**self).get(key_id)
+    }
+    fn put(&self, key: &ArchivedKey) {
+        (**self).put(key)
+    }
+    fn put_all(&self, keys: &[ArchivedKey]) {
+        (**self).put_all(keys)
+    }
+}
+
+/// Adds the keys of a key set that was loaded at `seen_at` and expires at `valid_until`, in a
+/// single batch. Entries that would not change are left alone.
+pub(crate) fn record(
+    archive: &dyn KeyArchive,
+    keys: &[JsonWebKey],
+    seen_at: SystemTime,
+    valid_until: SystemTime,
+) {
+    let changed: Vec<ArchivedKey> = keys
+        .iter()
+        .filter_map(|key| {
+            let previous = archive.get(&key.get_id());
+            let archived = match previous {
+                Some(ref archived) => ArchivedKey {
+                    key: key.clone(),
+                    first_seen: archived.first_seen.min(seen_at),
+                    last_seen: archived.last_seen.max(seen_at),
+                    valid_until: archived.valid_until.max(valid_until),
+                },
+                None => ArchivedKey {
+                    key: key.clone(),
+                    first_seen: seen_at,
+                    last_seen: seen_at,
+                    valid_until,
+                },
+            };
+            match previous {
+                Some(ref previous) if previous.is_same_as(&archived) => None,
+                _ => Some(archived),
+            }
+        })
+        .collect();
+    if !changed.is_empty() {
+        archive.put_all(&changed);
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryKeyArchive {
+    keys: Mutex<HashMap<String, ArchivedKey>>,
+}
+
+impl MemoryKeyArchive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyArchive for MemoryKeyArchive {
+    fn get(&self, key_id: &str) -> Option<ArchivedKey> {
+        self.keys.lock().unwrap().get(key_id).cloned()
+    }
+    fn put(&self, key: &ArchivedKey) {
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(key.key.get_id(), key.clone());
+    }
+}
+
+/// Keeps the archive in a JSON file, which is rewritten on every change. Several processes can
+/// share the file: writers take an advisory lock on `<path>.lock` while they merge their keys
+/// into it.
+pub struct FileKeyArchive {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedKey {
+    key: JsonWebKey,
+    first_seen: u64,
+    last_seen: u64,
+    valid_until: u64,
+}
+
+impl FileKeyArchive {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    fn read(&self) -> HashMap<String, PersistedKey> {
+        fs::read(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, archived: &[ArchivedKey]) -> Result<(), ()> {
+        // The file is replaced by a rename, so the lock is taken on a file next to it. Released
+        // when `lock` is dropped.
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.lock_path())
+            .map_err(|_| ())?;
+        lock.lock().map_err(|_| ())?;
+        let mut keys = self.read();
+        for key in archived {
+            let mut persisted = PersistedKey {
+                key: key.key.clone(),
+                first_seen: to_timestamp(key.first_seen)?,
+                last_seen: to_timestamp(key.last_seen)?,
+                valid_until: to_timestamp(key.valid_until)?,
+            };
+            // Another process may have seen the key earlier, or later.
+            if let Some(existing) = keys.get(&key.key.get_id()) {
+                persisted.first_seen = persisted.first_seen.min(existing.first_seen);
+                persisted.last_seen = persisted.last_seen.max(existing.last_seen);
+                persisted.valid_until = persisted.valid_until.max(existing.valid_until);
+            }
+            keys.insert(key.key.get_id(), persisted);
+        }
+        write_atomically(&self.path, &serde_json::to_vec(&keys).map_err(|_| ())?)
+    }
+}
+
+impl KeyArchive for FileKeyArchive {
+    fn get(&self, key_id: &str) -> Option<ArchivedKey> {
+        let persisted = self.read().remove(key_id)?;
+        Some(ArchivedKey {
+            key: persisted.key,
+            first_seen: from_timestamp(persisted.first_seen),
+            last_seen: from_timestamp(persisted.last_seen),
+            valid_until: from_timestamp(persisted.valid_until),
+        })
+    }
+    fn put(&self, key: &ArchivedKey) {
+        let _ = self.write(std::slice::from_ref(key));
+    }
+    fn put_all(&self, keys: &[ArchivedKey]) {
+        let _ = self.write(keys);
+    }
+}
+
+fn to_timestamp(time: SystemTime) -> Result<u64, ()> {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .map_err(|_| ())
+}
+
+fn from_timestamp(timestamp: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(timestamp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{record, ArchivedKey, FileKeyArchive, KeyArchive, MemoryKeyArchive};
+    use crate::jwk::JsonWebKeySet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+
+    const JWKS: &str = r#"{"keys": [
+        {"kty": "RSA", "alg": "RS256", "kid": "first", "n": "", "e": "AQAB"},
+        {"kty": "RSA", "alg": "RS256", "kid": "second", "n": "", "e": "AQAB"}
+    ]}"#;
+
+    #[derive(Default)]
+    struct CountingArchive {
+        keys: MemoryKeyArchive,
+        batches: AtomicUsize,
+    }
+
+    impl KeyArchive for CountingArchive {
+        fn get(&self, key_id: &str) -> Option<ArchivedKey> {
+            self.keys.get(key_id)
+        }
+        fn put(&self, key: &ArchivedKey) {
+            self.put_all(std::slice::from_ref(key));
+        }
+        fn put_all(&self, keys: &[ArchivedKey]) {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            self.keys.put_all(keys);
+        }
+    }
+
+    #[test]
+    fn test_record_writes_changes_once() {
+        let key_set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+        let archive = CountingArchive::default();
+        let seen_at = SystemTime::now();
+        let valid_until = seen_at + Duration::from_secs(3600);
+        record(&archive, key_set.get_keys(), seen_at, valid_until);
+        assert_eq!(archive.batches.load(Ordering::SeqCst), 1);
+        record(&archive, key_set.get_keys(), seen_at, valid_until);
+        assert_eq!(archive.batches.load(Ordering::SeqCst), 1);
+        record(&archive, key_set.get_keys(), valid_until, valid_until);
+        assert_eq!(archive.batches.load(Ordering::SeqCst), 2);
+        assert_eq!(archive.get("second").unwrap().last_seen, valid_until);
+    }
+
+    #[test]
+    fn test_file_archive_put_all() {
+        let key_set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+        let path = std::env::temp_dir().join(format!("jwks-batch-{}.json", std::process::id()));
+        let archive = FileKeyArchive::new(&path);
+        let now = SystemTime::now();
+        record(
+            &archive,
+            key_set.get_keys(),
+            now,
+            now + Duration::from_secs(60),
+        );
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        // Nothing changed at the archive's precision, so the file is not rewritten.
+        record(
+            &archive,
+            key_set.get_keys(),
+            now,
+            now + Duration::from_secs(60),
+        );
+        let unchanged = std::fs::metadata(&path).unwrap().modified().unwrap() == modified;
+        let (first, second) = (archive.get("first"), archive.get("second"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(first.is_some() && second.is_some());
+        assert!(unchanged);
+    }
+
+    #[test]
+    fn test_file_archive_shared_between_writers() {
+        let path = std::env::temp_dir().join(format!("jwks-shared-{}.json", std::process::id()));
+        let now = SystemTime::now();
+        // One archive per thread, as separate processes would have.
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let archive = FileKeyArchive::new(&path);
+                    for round in 0..10 {
+                        let key_set: JsonWebKeySet = serde_json::from_value(serde_json::json!({
+                            "keys": [{
+                                "kty": "RSA",
+                                "alg": "RS256",
+                                "kid": format!("{}-{}", writer, round),
+                                "n": "",
+                                "e": "AQAB",
+                            }],
+                        }))
+                        .unwrap();
+                        record(&archive, key_set.get_keys(), now, now);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let archive = FileKeyArchive::new(&path);
+        let missing = (0..8)
+            .flat_map(|writer| (0..10).map(move |round| format!("{}-{}", writer, round)))
+            .filter(|key_id| archive.get(key_id).is_none())
+            .count();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(archive.lock_path()).unwrap();
+        assert_eq!(missing, 0);
+    }
+}
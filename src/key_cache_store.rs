@@ -0,0 +1,131 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A key set as downloaded, in its JSON form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredKeySet {
+    pub key_set: String,
+    pub expires_at: SystemTime,
+}
+
+/// A cache that sits between a key provider and the network, and can be shared between
+/// processes. Entries are keyed by the URL the key set was downloaded from.
+pub trait KeyCacheStore: Send + Sync {
+    fn get(&self, source: &str) -> Option<StoredKeySet>;
+    fn put(&self, source: &str, key_set: &StoredKeySet);
+}
+
+impl<S: KeyCacheStore + ?Sized> KeyCacheStore for Arc<S> {
+    fn get(&self, source: &str) -> Option<StoredKeySet> {
+        (**self).get(source)
+    }
+    fn put(&self, source: &str, key_set: &StoredKeySet) {
+        (**self).put(source, key_set)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryKeyCacheStore {
+    entries: Mutex<HashMap<String, StoredKeySet>>,
+}
+
+impl MemoryKeyCacheStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyCacheStore for MemoryKeyCacheStore {
+    fn get(&self, source: &str) -> Option<StoredKeySet> {
+        self.entries.lock().unwrap().get(source).cloned()
+    }
+    fn put(&self, source: &str, key_set: &StoredKeySet) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(source.to_owned(), key_set.clone());
+    }
+}
+
+/// Keeps the most recent key set in a single file.
+pub struct FileKeyCacheStore {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedKeySet {
+    source: String,
+    expires_at: u64,
+    key_set: String,
+}
+
+impl FileKeyCacheStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn write(&self, source: &str, key_set: &StoredKeySet) -> Result<(), ()> {
+        let expires_at = key_set
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| ())?;
+        let persisted = PersistedKeySet {
+            source: source.to_owned(),
+            expires_at: expires_at.as_secs(),
+            key_set: key_set.key_set.clone(),
+        };
+        write_atomically(&self.path, &serde_json::to_vec(&persisted).map_err(|_| ())?)
+    }
+}
+
+/// Writes the file next to its final location first, so readers never see a partial file. The
+/// temporary name is unique to the writer, so processes sharing the file cannot truncate each
+/// other's.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), ()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(
+        ".{}.{:016x}.tmp",
+        std::process::id(),
+        random_suffix()
+    ));
+    let result = fs::write(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(|_| ())
+}
+
+fn random_suffix() -> u64 {
+    // Every `RandomState` gets different keys, which are randomly seeded per thread.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+impl KeyCacheStore for FileKeyCacheStore {
+    fn get(&self, source: &str) -> Option<StoredKeySet> {
+        let persisted: PersistedKeySet =
+            serde_json::from_slice(&fs::read(&self.path).ok()?).ok()?;
+        if persisted.source != source {
+            return None;
+        }
+        Some(StoredKeySet {
+            key_set: persisted.key_set,
+            expires_at: UNIX_EPOCH + Duration::from_secs(persisted.expires_at),
+        })
+    }
+    fn put(&self, source: &str, key_set: &StoredKeySet) {
+        let _ = self.write(source, key_set);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::jwk::JsonWebKeySet;
+
+/// Changes to the keys of a `GoogleKeyProvider`, see `GoogleKeyProvider::on_key_event`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// A refresh brought keys that were not in the previous key set.
+    KeysAdded(Vec<String>),
+    /// A refresh dropped keys that were in the previous key set.
+    KeysRemoved(Vec<String>),
+    /// A refresh brought keys that do not meet the provider's key policy, and were left out.
+    KeysRejected(Vec<String>),
+    /// A download failed, or returned something that is not a key set.
+    RefreshFailed,
+    /// A key was served from an expired key set, or from the fallback key set.
+    StaleKeyUsed(String),
+}
+
+pub type KeyEventListener = Box<dyn Fn(&KeyEvent) + Send + Sync>;
+
+pub fn key_changes(previous: &JsonWebKeySet, current: &JsonWebKeySet) -> Vec<KeyEvent> {
+    let previous_ids = previous.get_key_ids();
+    let current_ids = current.get_key_ids();
+    let added: Vec<String> = current_ids
+        .iter()
+        .filter(|id| !previous_ids.contains(id))
+        .cloned()
+        .collect();
+    let removed: Vec<String> = previous_ids
+        .iter()
+        .filter(|id| !current_ids.contains(id))
+        .cloned()
+        .collect();
+    let mut events = Vec::new();
+    if !added.is_empty() {
+        events.push(KeyEvent::KeysAdded(added));
+    }
+    if !removed.is_empty() {
+        events.push(KeyEvent::KeysRemoved(removed));
+    }
+    events
+}
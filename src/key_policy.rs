@@ -0,0 +1,50 @@
+use crate::jwk::{JsonWebKey, KeyType};
+
+/// Minimum strength for the keys a provider loads. Keys that fall short are left out of the
+/// key set, so they can never verify a token.
+#[derive(Clone, Debug)]
+pub struct KeyPolicy {
+    min_rsa_bits: u32,
+    allowed_curves: Vec<String>,
+}
+
+impl Default for KeyPolicy {
+    /// 2048 bit RSA, and the P-256, P-384 and P-521 curves.
+    fn default() -> Self {
+        Self {
+            min_rsa_bits: 2048,
+            allowed_curves: vec!["P-256".to_owned(), "P-384".to_owned(), "P-521".to_owned()],
+        }
+    }
+}
+
+impl KeyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min_rsa_bits(mut self, bits: u32) -> Self {
+        self.min_rsa_bits = bits;
+        self
+    }
+
+    pub fn with_allowed_curves(mut self, curves: &[&str]) -> Self {
+        self.allowed_curves = curves.iter().map(|&curve| curve.to_owned()).collect();
+        self
+    }
+
+    /// Keys that cannot be parsed are not accepted either.
+    pub fn accepts(&self, key: &JsonWebKey) -> bool {
+        let public_key = match key.public_key() {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        match key.get_key_type() {
+            KeyType::Rsa => public_key.bits() >= self.min_rsa_bits,
+            KeyType::Ec => key
+                .get_curve()
+                .is_some_and(|curve| self.allowed_curves.iter().any(|allowed| allowed == curve)),
+            KeyType::Symmetric => false,
+        }
+    }
+}
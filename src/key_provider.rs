@@ -1,109 +1,1269 @@
+use crate::error::Error;
 use crate::jwk::JsonWebKey;
 use crate::jwk::JsonWebKeySet;
+use crate::key_archive::{self, KeyArchive};
+use crate::key_cache_store::{FileKeyCacheStore, KeyCacheStore, StoredKeySet};
+use crate::key_event::{self, KeyEvent, KeyEventListener};
+use crate::key_policy::KeyPolicy;
+use crate::key_status::{FetchError, FetchFailure, KeyHealth, KeyInfo, KeyStatus};
+use crate::x509;
 #[cfg(feature = "async")]
 use async_trait::async_trait;
+#[cfg(feature = "async")]
+use futures::future::{BoxFuture, FutureExt, Shared};
 use headers::{Header, HeaderMap};
 use reqwest::header::CACHE_CONTROL;
-use std::time::Instant;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 const GOOGLE_CERT_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const GOOGLE_X509_CERT_URL: &str = "https://www.googleapis.com/oauth2/v1/certs";
+const GOOGLE_SERVICE_ACCOUNT_CERT_URL: &str = "https://www.googleapis.com/robot/v1/metadata/x509/";
+const DEFAULT_REFETCH_COOLDOWN: Duration = Duration::from_secs(60);
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_STALE: Duration = Duration::from_secs(60 * 60);
+// Unknown key ids come from tokens, so anyone can make up as many as they like.
+const MAX_UNKNOWN_KEY_IDS: usize = 1024;
 
 #[cfg(feature = "blocking")]
-pub trait KeyProvider {
-    fn get_key(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()>;
+#[allow(clippy::result_unit_err)]
+pub trait KeyProvider: Send + Sync {
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()>;
+
+    /// Loads the keys ahead of the first verification. Providers without a remote source have
+    /// nothing to load.
+    fn prefetch_keys(&self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    /// The ids of the keys the provider currently holds, without loading any. Used to explain
+    /// failed verifications; providers that cannot list their keys return none.
+    fn get_key_ids(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 #[cfg(feature = "async")]
 #[async_trait]
-pub trait AsyncKeyProvider {
-    async fn get_key_async(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()>;
+pub trait AsyncKeyProvider: Send + Sync {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()>;
+
+    async fn prefetch_keys_async(&self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn get_key_ids_async(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A key set as received from Google or from the cache store.
+#[derive(Clone)]
+pub(crate) struct FetchedKeys {
+    text: String,
+    time_to_live: Duration,
+    from_store: bool,
+}
+
+#[cfg(feature = "async")]
+type SharedFetch = Shared<BoxFuture<'static, Result<FetchedKeys, ()>>>;
+
+#[derive(Clone, Copy)]
+enum KeySetFormat {
+    JsonWebKeys,
+    X509Certificates,
 }
 
 pub struct GoogleKeyProvider {
-    cached: Option<JsonWebKeySet>,
+    url: String,
+    format: KeySetFormat,
+    refetch_cooldown: Duration,
+    max_stale: Duration,
+    cache_store: Option<Arc<dyn KeyCacheStore>>,
+    fallback: Option<JsonWebKeySet>,
+    archive: Option<Arc<dyn KeyArchive>>,
+    key_policy: Option<KeyPolicy>,
+    listeners: Vec<KeyEventListener>,
+    // Swapped as a whole on every download, so lookups only hold the read lock for a clone.
+    cached: RwLock<Option<Arc<CachedKeys>>>,
+    fetch_state: Mutex<FetchState>,
+    #[cfg(feature = "blocking")]
+    download_lock: Mutex<()>,
+    #[cfg(feature = "async")]
+    in_flight: Mutex<Option<SharedFetch>>,
+}
+
+struct CachedKeys {
+    key_set: JsonWebKeySet,
     expiration_time: Instant,
+    expires_at: SystemTime,
+}
+
+/// A key set that replaced the cached keys, with what is left to do once no lock is held.
+struct LoadedKeys {
+    keys: Arc<CachedKeys>,
+    events: Vec<KeyEvent>,
+    /// The downloaded key set, to be written to the cache store.
+    to_store: Option<StoredKeySet>,
+}
+
+struct FetchState {
+    last_fetch: Option<Instant>,
+    unknown_key_ids: HashSet<String>,
+    last_success: Option<SystemTime>,
+    last_error: Option<FetchError>,
+    successful_fetches: u64,
+    failed_fetches: u64,
 }
 
 impl Default for GoogleKeyProvider {
     fn default() -> Self {
         Self {
-            cached: None,
-            expiration_time: Instant::now(),
+            url: GOOGLE_CERT_URL.to_owned(),
+            format: KeySetFormat::JsonWebKeys,
+            refetch_cooldown: DEFAULT_REFETCH_COOLDOWN,
+            max_stale: DEFAULT_MAX_STALE,
+            cache_store: None,
+            fallback: None,
+            archive: None,
+            key_policy: None,
+            listeners: Vec::new(),
+            cached: RwLock::new(None),
+            fetch_state: Mutex::new(FetchState {
+                last_fetch: None,
+                unknown_key_ids: HashSet::new(),
+                last_success: None,
+                last_error: None,
+                successful_fetches: 0,
+                failed_fetches: 0,
+            }),
+            #[cfg(feature = "blocking")]
+            download_lock: Mutex::new(()),
+            #[cfg(feature = "async")]
+            in_flight: Mutex::new(None),
         }
     }
 }
 
+impl FetchState {
+    /// Once the set is full, unknown key ids are only held back by the refetch cooldown.
+    fn remember_unknown_key(&mut self, key_id: &str) {
+        if self.unknown_key_ids.len() < MAX_UNKNOWN_KEY_IDS {
+            self.unknown_key_ids.insert(key_id.to_owned());
+        }
+    }
+}
+
+impl CachedKeys {
+    fn is_fresh(&self) -> bool {
+        self.expiration_time > Instant::now()
+    }
+}
+
 impl GoogleKeyProvider {
-    fn process_response(&mut self, headers: &HeaderMap, text: &str) -> Result<&JsonWebKeySet, ()> {
-        let mut expiration_time = None;
-        let x = headers.get_all(CACHE_CONTROL);
-        if let Ok(cache_header) = headers::CacheControl::decode(&mut x.iter()) {
-            if let Some(max_age) = cache_header.max_age() {
-                expiration_time = Some(Instant::now() + max_age);
+    /// Downloads PEM certificates keyed by key id, in the format of
+    /// `https://www.googleapis.com/oauth2/v1/certs`, instead of JSON web keys.
+    pub fn x509_certificates(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            format: KeySetFormat::X509Certificates,
+            ..Self::default()
+        }
+    }
+    /// Google's signing certificates, the X.509 counterpart of the default key set.
+    pub fn google_x509_certificates() -> Self {
+        Self::x509_certificates(GOOGLE_X509_CERT_URL)
+    }
+    /// The certificates of a service account, for tokens the account signed itself.
+    pub fn service_account(email: &str) -> Self {
+        Self::x509_certificates(&format!("{}{}", GOOGLE_SERVICE_ACCOUNT_CERT_URL, email))
+    }
+    /// Calls `listener` when keys are added or removed by a refresh, when a refresh fails, and
+    /// when an expired key is used. Listeners run on the thread that caused the event, and
+    /// should return quickly.
+    pub fn on_key_event<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&KeyEvent) + Send + Sync + 'static,
+    {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+    fn emit(&self, event: KeyEvent) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+    }
+    /// Minimum time between two downloads triggered by an unknown key id.
+    pub fn with_refetch_cooldown(mut self, cooldown: Duration) -> Self {
+        self.refetch_cooldown = cooldown;
+        self
+    }
+    /// How long after they expire the keys may still be used while no new keys can be
+    /// downloaded. Past that, lookups fail and the health is `Unhealthy`. An hour by default.
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+    fn current_keys(&self) -> Option<Arc<CachedKeys>> {
+        self.cached.read().unwrap().clone()
+    }
+    fn lock_fetch_state(&self) -> MutexGuard<'_, FetchState> {
+        self.fetch_state.lock().unwrap()
+    }
+    /// Answers from `key_set` for as long as no key set could be downloaded, e.g. a snapshot
+    /// bundled with `include_str!` for offline tests or disaster recovery. Once a download has
+    /// succeeded the snapshot is never used again.
+    pub fn with_fallback_key_set(mut self, key_set: &str) -> Result<Self, Error> {
+        let mut key_set: JsonWebKeySet =
+            serde_json::from_str(key_set).map_err(|_| Error::RetrieveKeyFailure)?;
+        if let Some(ref policy) = self.key_policy {
+            key_set.apply_policy(policy);
+        }
+        key_set.prepare_keys();
+        self.fallback = Some(key_set);
+        Ok(self)
+    }
+    /// Leaves keys that do not meet `policy` out of every key set this provider loads,
+    /// including the fallback key set and keys already loaded from a cache store.
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        if let Some(ref mut fallback) = self.fallback {
+            fallback.apply_policy(&policy);
+        }
+        let cached = self.cached.get_mut().unwrap();
+        if let Some(keys) = cached.take() {
+            let mut key_set = keys.key_set.clone();
+            key_set.apply_policy(&policy);
+            *cached = Some(Arc::new(CachedKeys {
+                key_set,
+                expiration_time: keys.expiration_time,
+                expires_at: keys.expires_at,
+            }));
+        }
+        self.key_policy = Some(policy);
+        self
+    }
+    /// Uses `store` as a second cache level: every download first looks for a set in the store
+    /// that expires later than ours, and keys downloaded from Google are written back to it.
+    pub fn with_cache_store<S: KeyCacheStore + 'static>(mut self, store: S) -> Self {
+        if let Some(stored) = store.get(&self.url) {
+            if let Ok(loaded) = self.load_key_set(&stored.key_set, stored.expires_at) {
+                self.persist(&loaded);
+            }
+        }
+        self.cache_store = Some(Arc::new(store));
+        self
+    }
+    /// Keeps the last downloaded keys in a file, and starts from that file if it exists. Cold
+    /// starts can then skip the download for as long as the saved keys have not expired.
+    pub fn with_disk_cache<P: Into<PathBuf>>(self, path: P) -> Self {
+        self.with_cache_store(FileKeyCacheStore::new(path))
+    }
+    /// Records every key this provider loads, so tokens signed with keys that have since been
+    /// rotated out can still be checked with `verify_archived_token_with_payload`.
+    pub fn with_key_archive<A: KeyArchive + 'static>(mut self, archive: A) -> Self {
+        self.archive = Some(Arc::new(archive));
+        self
+    }
+    fn process_response(&self, fetched: &FetchedKeys) -> Result<LoadedKeys, ()> {
+        let expires_at = SystemTime::now() + fetched.time_to_live;
+        let mut loaded = self.load_key_set(&fetched.text, expires_at)?;
+        if self.cache_store.is_some() && !fetched.from_store {
+            loaded.to_store = Some(StoredKeySet {
+                key_set: fetched.text.clone(),
+                expires_at: loaded.keys.expires_at,
+            });
+        }
+        Ok(loaded)
+    }
+    /// Replaces the cached keys, and returns how they changed. Nothing is reported for the
+    /// first key set.
+    fn load_key_set(&self, text: &str, expires_at: SystemTime) -> Result<LoadedKeys, ()> {
+        let mut key_set = match self.format {
+            KeySetFormat::JsonWebKeys => {
+                serde_json::from_str::<JsonWebKeySet>(text).map_err(|_| ())?
             }
+            KeySetFormat::X509Certificates => x509::parse_certificate_map(text)?,
+        };
+        let rejected = match self.key_policy {
+            Some(ref policy) => key_set.apply_policy(policy),
+            None => Vec::new(),
+        };
+        key_set.prepare_keys();
+        let current = Arc::new(CachedKeys {
+            key_set,
+            expiration_time: Instant::now() + time_left(expires_at),
+            expires_at,
+        });
+        let previous = self.cached.write().unwrap().replace(current.clone());
+        let mut events = Vec::new();
+        if !rejected.is_empty() {
+            events.push(KeyEvent::KeysRejected(rejected));
         }
-        let key_set = serde_json::from_str(&text).map_err(|_| ())?;
-        if let Some(expiration_time) = expiration_time {
-            self.cached = Some(key_set);
-            self.expiration_time = expiration_time;
+        if let Some(previous) = previous {
+            events.extend(key_event::key_changes(&previous.key_set, &current.key_set));
+        }
+        Ok(LoadedKeys {
+            keys: current,
+            events,
+            to_store: None,
+        })
+    }
+    /// Writes loaded keys to the cache store and the archive. Both may do I/O, so this is only
+    /// called without holding a lock.
+    fn persist(&self, loaded: &LoadedKeys) {
+        self.persist_job(loaded)()
+    }
+    /// The writes of `persist`, detached from the provider so the async path can run them on
+    /// a blocking thread.
+    fn persist_job(&self, loaded: &LoadedKeys) -> impl FnOnce() + Send + 'static {
+        let store = self.cache_store.clone().zip(loaded.to_store.clone());
+        let archive = self.archive.clone();
+        let url = self.url.clone();
+        let keys = loaded.keys.clone();
+        move || {
+            if let Some((store, stored)) = store {
+                store.put(&url, &stored);
+            }
+            if let Some(archive) = archive {
+                key_archive::record(
+                    archive.as_ref(),
+                    keys.key_set.get_keys(),
+                    SystemTime::now(),
+                    keys.expires_at,
+                );
+            }
         }
-        Ok(self.cached.as_ref().unwrap())
     }
     #[cfg(feature = "blocking")]
-    pub fn download_keys(&mut self) -> Result<&JsonWebKeySet, ()> {
-        let result = reqwest::blocking::get(GOOGLE_CERT_URL).map_err(|_| ())?;
-        self.process_response(&result.headers().clone(), &result.text().map_err(|_| ())?)
+    fn fetch(&self) -> Result<FetchedKeys, ()> {
+        let stored = self
+            .cache_store
+            .as_ref()
+            .and_then(|store| fetch_from_store(store.as_ref(), &self.url, self.current_keys()));
+        match stored {
+            Some(fetched) => Ok(fetched),
+            None => fetch_keys(&self.url),
+        }
+    }
+    /// Only builds the future, the cache store is read once it is polled. `KeyCacheStore` is
+    /// synchronous, so the read runs on a blocking thread instead of the executor.
+    #[cfg(feature = "async")]
+    pub(crate) fn fetch_async(&self) -> BoxFuture<'static, Result<FetchedKeys, ()>> {
+        let store = self.cache_store.clone();
+        let url = self.url.clone();
+        let current_keys = self.current_keys();
+        async move {
+            if let Some(store) = store {
+                let source = url.clone();
+                let stored = tokio::task::spawn_blocking(move || {
+                    fetch_from_store(store.as_ref(), &source, current_keys)
+                })
+                .await;
+                if let Ok(Some(fetched)) = stored {
+                    return Ok(fetched);
+                }
+            }
+            fetch_keys_async(url).await
+        }
+        .boxed()
+    }
+    /// Answers a lookup from the cache, or returns `None` if the keys have to be downloaded.
+    fn get_cached_key(&self, key_id: &str) -> Option<Option<JsonWebKey>> {
+        let cached_keys = self.current_keys()?;
+        if !cached_keys.is_fresh() {
+            return None;
+        }
+        if let Some(key) = cached_keys.key_set.get_key(key_id) {
+            return Some(Some(key));
+        }
+        let mut fetch_state = self.lock_fetch_state();
+        if fetch_state.unknown_key_ids.contains(key_id) || !self.can_refetch(&fetch_state) {
+            fetch_state.remember_unknown_key(key_id);
+            return Some(None);
+        }
+        None
+    }
+    fn get_stale_key(&self, key_id: &str) -> Option<JsonWebKey> {
+        let key = self
+            .current_keys()
+            .filter(|keys| !self.is_too_stale(keys))
+            .and_then(|keys| keys.key_set.get_key(key_id))?;
+        self.emit(KeyEvent::StaleKeyUsed(key_id.to_owned()));
+        Some(key)
+    }
+    /// Answers from the fallback key set, if there is one and no fetch has succeeded yet. Keys
+    /// loaded from a cache store when the provider was built do not count, they may have
+    /// expired long ago.
+    fn get_fallback_key(&self, key_id: &str) -> Option<Option<JsonWebKey>> {
+        let fallback = self.fallback.as_ref()?;
+        if self.lock_fetch_state().last_success.is_some() {
+            return None;
+        }
+        let key = fallback.get_key(key_id);
+        if key.is_some() {
+            self.emit(KeyEvent::StaleKeyUsed(key_id.to_owned()));
+        }
+        Some(key)
+    }
+    /// Answers from the expired keys, or from the fallback key set, when no keys can be
+    /// downloaded.
+    fn get_key_offline(&self, key_id: &str) -> Option<Option<JsonWebKey>> {
+        match self.get_stale_key(key_id) {
+            Some(key) => Some(Some(key)),
+            None => self.get_fallback_key(key_id),
+        }
+    }
+    /// Answers without another download while the last one failed recently. Key ids missing
+    /// from the expired keys are not looked for either, and keys past the maximum staleness
+    /// fail the lookup.
+    fn get_key_after_failure(&self, key_id: &str) -> Option<Result<Option<JsonWebKey>, ()>> {
+        if self.can_refetch(&self.lock_fetch_state()) {
+            return None;
+        }
+        match self.get_key_offline(key_id) {
+            Some(key) => Some(Ok(key)),
+            None => self.current_keys().map(|keys| {
+                if self.is_too_stale(&keys) {
+                    Err(())
+                } else {
+                    Ok(None)
+                }
+            }),
+        }
+    }
+    fn is_too_stale(&self, keys: &CachedKeys) -> bool {
+        keys.expires_at
+            .checked_add(self.max_stale)
+            .is_some_and(|limit| limit < SystemTime::now())
+    }
+    fn finish_lookup(
+        &self,
+        key_id: &str,
+        downloaded: Result<(), ()>,
+    ) -> Result<Option<JsonWebKey>, ()> {
+        match downloaded {
+            Ok(()) => Ok(self.get_downloaded_key(key_id)),
+            Err(()) => self.get_key_offline(key_id).ok_or(()),
+        }
+    }
+    fn can_refetch(&self, fetch_state: &FetchState) -> bool {
+        match fetch_state.last_fetch {
+            Some(last_fetch) => last_fetch.elapsed() >= self.refetch_cooldown,
+            None => true,
+        }
+    }
+    fn has_fresh_keys(&self) -> bool {
+        self.current_keys().is_some_and(|keys| keys.is_fresh())
+    }
+    fn known_key_ids(&self) -> Vec<String> {
+        match (self.current_keys(), self.fallback.as_ref()) {
+            (Some(keys), _) => keys.key_set.get_key_ids(),
+            (None, Some(fallback)) => fallback.get_key_ids(),
+            (None, None) => Vec::new(),
+        }
+    }
+    fn get_downloaded_key(&self, key_id: &str) -> Option<JsonWebKey> {
+        let key = self
+            .current_keys()
+            .and_then(|keys| keys.key_set.get_key(key_id));
+        if key.is_none() {
+            self.lock_fetch_state().remember_unknown_key(key_id);
+        }
+        key
+    }
+    pub fn key_status(&self) -> KeyStatus {
+        let current_keys = self.current_keys();
+        let fetch_state = self.lock_fetch_state();
+        KeyStatus {
+            keys: current_keys
+                .as_ref()
+                .map(|keys| {
+                    keys.key_set
+                        .get_keys()
+                        .iter()
+                        .map(|key| KeyInfo {
+                            id: key.get_id(),
+                            algorithm: key.get_algorithm(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            expires_at: current_keys.map(|keys| keys.expires_at),
+            last_success: fetch_state.last_success,
+            last_error: fetch_state.last_error.clone(),
+            successful_fetches: fetch_state.successful_fetches,
+            failed_fetches: fetch_state.failed_fetches,
+        }
+    }
+    pub fn health(&self) -> KeyHealth {
+        match self.current_keys() {
+            Some(ref keys) if keys.is_fresh() => KeyHealth::Healthy,
+            Some(ref keys) if !self.is_too_stale(keys) => KeyHealth::Degraded,
+            _ if self.fallback.is_some() && self.lock_fetch_state().last_success.is_none() => {
+                KeyHealth::Degraded
+            }
+            _ => KeyHealth::Unhealthy,
+        }
+    }
+    /// How long the background refresher should wait before its next download.
+    pub(crate) fn refresh_delay(&self) -> Duration {
+        let until_refresh = match self.current_keys() {
+            Some(keys) => keys
+                .expiration_time
+                .saturating_duration_since(Instant::now())
+                .saturating_sub(REFRESH_MARGIN),
+            None => Duration::from_secs(0),
+        };
+        let until_retry = match self.lock_fetch_state().last_fetch {
+            Some(last_fetch) => MIN_REFRESH_INTERVAL.saturating_sub(last_fetch.elapsed()),
+            None => Duration::from_secs(0),
+        };
+        until_refresh.max(until_retry)
+    }
+    #[cfg(test)]
+    fn store_response(&self, response: Result<FetchedKeys, ()>) -> Result<(), ()> {
+        let update = self.update_keys(response);
+        self.publish(update)
+    }
+    /// Records the outcome of a download and swaps in the new keys. Only memory is touched, so
+    /// other locks may be held while calling this.
+    fn update_keys(&self, response: Result<FetchedKeys, ()>) -> Result<LoadedKeys, ()> {
+        let mut fetch_state = self.lock_fetch_state();
+        fetch_state.last_fetch = Some(Instant::now());
+        let loaded = match response {
+            Ok(fetched) => self
+                .process_response(&fetched)
+                .map_err(|()| FetchFailure::InvalidKeySet),
+            Err(()) => Err(FetchFailure::Download),
+        };
+        match loaded {
+            Ok(loaded) => {
+                fetch_state.unknown_key_ids.clear();
+                fetch_state.last_success = Some(SystemTime::now());
+                fetch_state.successful_fetches += 1;
+                Ok(loaded)
+            }
+            Err(reason) => {
+                fetch_state.last_error = Some(FetchError {
+                    time: SystemTime::now(),
+                    reason,
+                });
+                fetch_state.failed_fetches += 1;
+                Err(())
+            }
+        }
+    }
+    /// Writes out the keys of an update and calls the listeners. No lock may be held, so the
+    /// cache store, archive and listeners can all use the provider.
+    #[cfg(any(feature = "blocking", test))]
+    fn publish(&self, update: Result<LoadedKeys, ()>) -> Result<(), ()> {
+        if let Ok(ref loaded) = update {
+            self.persist(loaded);
+        }
+        self.announce(update)
+    }
+    fn announce(&self, update: Result<LoadedKeys, ()>) -> Result<(), ()> {
+        match update {
+            Ok(loaded) => {
+                for event in loaded.events {
+                    self.emit(event);
+                }
+                Ok(())
+            }
+            Err(()) => {
+                self.emit(KeyEvent::RefreshFailed);
+                Err(())
+            }
+        }
+    }
+    #[cfg(feature = "blocking")]
+    pub(crate) fn download_keys(&self) -> Result<(), ()> {
+        let update = {
+            let _download = self.download_lock.lock().unwrap();
+            self.update_keys(self.fetch())
+        };
+        self.publish(update)
+    }
+    #[cfg(feature = "blocking")]
+    fn get_key_blocking(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        if let Some(key) = self
+            .get_cached_key(key_id)
+            .map(Ok)
+            .or_else(|| self.get_key_after_failure(key_id))
+        {
+            return key;
+        }
+        let update = {
+            let _download = self.download_lock.lock().unwrap();
+            // Another thread may have finished a download while this one was waiting for the lock.
+            if let Some(key) = self.get_cached_key(key_id) {
+                return Ok(key);
+            }
+            self.update_keys(self.fetch())
+        };
+        let downloaded = self.publish(update);
+        self.finish_lookup(key_id, downloaded)
+    }
+    /// Joins the download that is already running, or starts a new one. The returned flag is
+    /// true if this caller started it.
+    #[cfg(feature = "async")]
+    fn join_fetch<F>(&self, fetch: F) -> (SharedFetch, bool)
+    where
+        F: FnOnce() -> BoxFuture<'static, Result<FetchedKeys, ()>>,
+    {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        match *in_flight {
+            Some(ref running) => (running.clone(), false),
+            None => {
+                let started = fetch().shared();
+                *in_flight = Some(started.clone());
+                (started, true)
+            }
+        }
+    }
+    /// Stores the result of a shared download. Only the first waiter to finish stores it, the
+    /// others find the cache already updated.
+    #[cfg(feature = "async")]
+    async fn finish_fetch(
+        &self,
+        fetch: &SharedFetch,
+        response: Result<FetchedKeys, ()>,
+    ) -> Result<(), ()> {
+        let update = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match *in_flight {
+                Some(ref running) if running.ptr_eq(fetch) => {
+                    *in_flight = None;
+                    self.update_keys(response)
+                }
+                _ => return response.map(|_| ()),
+            }
+        };
+        if let Ok(ref loaded) = update {
+            let _ = tokio::task::spawn_blocking(self.persist_job(loaded)).await;
+        }
+        self.announce(update)
+    }
+    #[cfg(feature = "async")]
+    pub(crate) async fn download_keys_async<F>(&self, fetch: F) -> Result<(), ()>
+    where
+        F: FnOnce() -> BoxFuture<'static, Result<FetchedKeys, ()>>,
+    {
+        let (shared, _) = self.join_fetch(fetch);
+        let response = shared.clone().await;
+        self.finish_fetch(&shared, response).await
     }
     #[cfg(feature = "async")]
-    async fn download_keys_async(&mut self) -> Result<&JsonWebKeySet, ()> {
-        let result = reqwest::get(GOOGLE_CERT_URL).await.map_err(|_| ())?;
-        self.process_response(
-            &result.headers().clone(),
-            &result.text().await.map_err(|_| ())?,
-        )
+    async fn get_key_shared<F>(&self, key_id: &str, fetch: F) -> Result<Option<JsonWebKey>, ()>
+    where
+        F: FnOnce() -> BoxFuture<'static, Result<FetchedKeys, ()>>,
+    {
+        if let Some(key) = self
+            .get_cached_key(key_id)
+            .map(Ok)
+            .or_else(|| self.get_key_after_failure(key_id))
+        {
+            return key;
+        }
+        let (shared, started) = self.join_fetch(fetch);
+        if !started {
+            // Someone else is already downloading, answer from the expired keys if possible.
+            if let Some(key) = self.get_stale_key(key_id) {
+                return Ok(Some(key));
+            }
+        }
+        let response = shared.clone().await;
+        let downloaded = self.finish_fetch(&shared, response).await;
+        self.finish_lookup(key_id, downloaded)
     }
 }
 
+/// Takes the keys from the cache store instead of Google when another process has already
+/// stored a set that expires later than ours. The refresher downloads before the keys expire,
+/// so fresh keys are replaced too; only one process has to go to Google.
+fn fetch_from_store(
+    store: &dyn KeyCacheStore,
+    url: &str,
+    current_keys: Option<Arc<CachedKeys>>,
+) -> Option<FetchedKeys> {
+    let stored = store.get(url)?;
+    let is_newer = match current_keys {
+        Some(keys) => stored.expires_at > keys.expires_at,
+        None => true,
+    };
+    let time_to_live = time_left(stored.expires_at);
+    if !is_newer || time_to_live == Duration::from_secs(0) {
+        return None;
+    }
+    Some(FetchedKeys {
+        text: stored.key_set,
+        time_to_live,
+        from_store: true,
+    })
+}
+
+fn time_left(expires_at: SystemTime) -> Duration {
+    expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_else(|_| Duration::from_secs(0))
+}
+
+fn time_to_live(headers: &HeaderMap) -> Duration {
+    // Without a max-age the keys are kept, but considered expired straight away.
+    let x = headers.get_all(CACHE_CONTROL);
+    headers::CacheControl::decode(&mut x.iter())
+        .ok()
+        .and_then(|cache_header| cache_header.max_age())
+        .unwrap_or_else(|| Duration::from_secs(0))
+}
+
+#[cfg(feature = "blocking")]
+fn fetch_keys(url: &str) -> Result<FetchedKeys, ()> {
+    let result = reqwest::blocking::get(url).map_err(|_| ())?;
+    let time_to_live = time_to_live(result.headers());
+    Ok(FetchedKeys {
+        text: result.text().map_err(|_| ())?,
+        time_to_live,
+        from_store: false,
+    })
+}
+
+#[cfg(feature = "async")]
+async fn fetch_keys_async(url: String) -> Result<FetchedKeys, ()> {
+    let result = reqwest::get(&url).await.map_err(|_| ())?;
+    let time_to_live = time_to_live(result.headers());
+    Ok(FetchedKeys {
+        text: result.text().await.map_err(|_| ())?,
+        time_to_live,
+        from_store: false,
+    })
+}
+
 #[cfg(feature = "blocking")]
 impl KeyProvider for GoogleKeyProvider {
-    fn get_key(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
-        if let Some(ref cached_keys) = self.cached {
-            if self.expiration_time > Instant::now() {
-                return Ok(cached_keys.get_key(key_id));
-            }
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        self.get_key_blocking(key_id)
+    }
+    fn prefetch_keys(&self) -> Result<(), ()> {
+        if !self.has_fresh_keys() {
+            self.download_keys()?;
         }
-        Ok(self.download_keys()?.get_key(key_id))
+        Ok(())
+    }
+    fn get_key_ids(&self) -> Vec<String> {
+        self.known_key_ids()
     }
 }
 
 #[cfg(feature = "async")]
 #[async_trait]
 impl AsyncKeyProvider for GoogleKeyProvider {
-    async fn get_key_async(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
-        if let Some(ref cached_keys) = self.cached {
-            if self.expiration_time > Instant::now() {
-                return Ok(cached_keys.get_key(key_id));
-            }
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        self.get_key_shared(key_id, || self.fetch_async()).await
+    }
+    async fn prefetch_keys_async(&self) -> Result<(), ()> {
+        if !self.has_fresh_keys() {
+            self.download_keys_async(|| self.fetch_async()).await?;
         }
-        Ok(self.download_keys_async().await?.get_key(key_id))
+        Ok(())
+    }
+    async fn get_key_ids_async(&self) -> Vec<String> {
+        self.known_key_ids()
     }
 }
 
 #[cfg(feature = "blocking")]
 #[test]
 pub fn test_google_provider() {
-    let mut provider = GoogleKeyProvider::default();
+    let provider = GoogleKeyProvider::default();
     assert!(provider.get_key("test").is_ok());
     assert!(provider.get_key("test").is_ok());
 }
 
+#[cfg(test)]
+mod cache_test {
+    use super::{
+        FetchFailure, FetchedKeys, GoogleKeyProvider, KeyEvent, KeyHealth, MAX_UNKNOWN_KEY_IDS,
+    };
+    use crate::algorithm::Algorithm;
+    use crate::key_archive::{KeyArchive, MemoryKeyArchive};
+    use crate::key_cache_store::{KeyCacheStore, MemoryKeyCacheStore, StoredKeySet};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    const JWKS: &str =
+        r#"{"keys": [{"kty": "RSA", "alg": "RS256", "kid": "known", "n": "", "e": "AQAB"}]}"#;
+
+    pub(super) fn downloaded(text: &str) -> FetchedKeys {
+        FetchedKeys {
+            text: text.to_owned(),
+            time_to_live: Duration::from_secs(3600),
+            from_store: false,
+        }
+    }
+
+    fn cached_provider(cooldown: Duration) -> GoogleKeyProvider {
+        let provider = GoogleKeyProvider::default().with_refetch_cooldown(cooldown);
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+        provider
+    }
+
+    #[test]
+    fn test_unknown_key_within_cooldown() {
+        let provider = cached_provider(Duration::from_secs(60));
+        assert!(provider.get_cached_key("known").unwrap().is_some());
+        assert!(provider.get_cached_key("unknown").unwrap().is_none());
+        assert!(provider
+            .lock_fetch_state()
+            .unknown_key_ids
+            .contains("unknown"));
+    }
+
+    #[test]
+    fn test_unknown_key_ids_are_bounded() {
+        let provider = cached_provider(Duration::from_secs(60));
+        for i in 0..10 * MAX_UNKNOWN_KEY_IDS {
+            let key_id = format!("forged-{}", i);
+            assert!(provider.get_cached_key(&key_id).unwrap().is_none());
+        }
+        assert_eq!(
+            provider.lock_fetch_state().unknown_key_ids.len(),
+            MAX_UNKNOWN_KEY_IDS
+        );
+    }
+
+    #[test]
+    fn test_unknown_key_after_cooldown() {
+        let provider = cached_provider(Duration::from_secs(0));
+        assert!(provider.get_cached_key("unknown").is_none());
+        assert!(provider.get_downloaded_key("unknown").is_none());
+        // Known to be missing from the current key set, so no further download.
+        assert!(provider.get_cached_key("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_disk_cache() {
+        let path = std::env::temp_dir().join(format!("google-jwks-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let provider = GoogleKeyProvider::default().with_disk_cache(&path);
+        assert!(!provider.has_fresh_keys());
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+
+        let restarted = GoogleKeyProvider::default().with_disk_cache(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(restarted.has_fresh_keys());
+        assert!(restarted.get_cached_key("known").unwrap().is_some());
+    }
+
+    fn read_store(provider: &GoogleKeyProvider) -> Option<FetchedKeys> {
+        let store = provider.cache_store.as_ref().unwrap();
+        super::fetch_from_store(store.as_ref(), &provider.url, provider.current_keys())
+    }
+
+    #[test]
+    fn test_shared_cache_store() {
+        let store = Arc::new(MemoryKeyCacheStore::new());
+        let first = GoogleKeyProvider::default().with_cache_store(store.clone());
+        let second = GoogleKeyProvider::default().with_cache_store(store.clone());
+        assert!(read_store(&second).is_none());
+
+        first.store_response(Ok(downloaded(JWKS))).unwrap();
+        let from_store = read_store(&second).unwrap();
+        assert!(from_store.from_store);
+        second.store_response(Ok(from_store)).unwrap();
+        assert!(second.get_cached_key("known").unwrap().is_some());
+        // Keys are only replaced by a set that expires later.
+        assert!(read_store(&second).is_none());
+    }
+
+    #[test]
+    fn test_refresh_takes_newer_keys_from_store() {
+        let store = Arc::new(MemoryKeyCacheStore::new());
+        let first = GoogleKeyProvider::default().with_cache_store(store.clone());
+        let second = GoogleKeyProvider::default().with_cache_store(store.clone());
+        first.store_response(Ok(downloaded(JWKS))).unwrap();
+        second
+            .store_response(Ok(read_store(&second).unwrap()))
+            .unwrap();
+
+        // Another process refreshed ahead of expiry, as the background refresher does.
+        let mut refreshed = downloaded(JWKS);
+        refreshed.time_to_live = Duration::from_secs(7200);
+        first.store_response(Ok(refreshed)).unwrap();
+        assert!(second.has_fresh_keys());
+        let from_store = read_store(&second).unwrap();
+        assert!(from_store.time_to_live > Duration::from_secs(3600));
+        second.store_response(Ok(from_store)).unwrap();
+        assert!(
+            second.key_status().expires_at > Some(SystemTime::now() + Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn test_fallback_key_set() {
+        let provider = GoogleKeyProvider::default()
+            .with_fallback_key_set(JWKS)
+            .unwrap();
+        assert!(provider.get_key_after_failure("known").is_none());
+        provider.store_response(Err(())).unwrap_err();
+        assert!(provider
+            .get_key_after_failure("known")
+            .unwrap()
+            .unwrap()
+            .is_some());
+        assert!(provider.finish_lookup("known", Err(())).unwrap().is_some());
+
+        provider
+            .store_response(Ok(downloaded(r#"{"keys": []}"#)))
+            .unwrap();
+        assert!(provider.get_fallback_key("known").is_none());
+        assert!(provider.finish_lookup("known", Err(())).is_err());
+    }
+
+    #[test]
+    fn test_fallback_key_set_with_expired_cache() {
+        let store = MemoryKeyCacheStore::new();
+        let cached =
+            r#"{"keys": [{"kty": "RSA", "alg": "RS256", "kid": "cached", "n": "", "e": "AQAB"}]}"#;
+        store.put(
+            super::GOOGLE_CERT_URL,
+            &StoredKeySet {
+                key_set: cached.to_owned(),
+                expires_at: SystemTime::now() - Duration::from_secs(3600),
+            },
+        );
+        let provider = GoogleKeyProvider::default()
+            .with_cache_store(store)
+            .with_fallback_key_set(JWKS)
+            .unwrap();
+        assert!(!provider.has_fresh_keys());
+        provider.store_response(Err(())).unwrap_err();
+        assert!(provider.finish_lookup("known", Err(())).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_x509_certificates() {
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let certificate = crate::x509::test::certificate(&key, now - 60, now + 3600);
+        let text = serde_json::json!({
+            "cert": String::from_utf8(certificate.to_pem().unwrap()).unwrap()
+        })
+        .to_string();
+        let provider = GoogleKeyProvider::service_account("robot@example.iam.gserviceaccount.com");
+        assert!(provider
+            .url
+            .ends_with("/x509/robot@example.iam.gserviceaccount.com"));
+        provider.store_response(Ok(downloaded(&text))).unwrap();
+        assert!(provider.get_cached_key("cert").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_key_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let provider = GoogleKeyProvider::default()
+            .on_key_event(move |event| recorded.lock().unwrap().push(event.clone()));
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+        provider.store_response(Err(())).unwrap_err();
+        let rotated =
+            r#"{"keys": [{"kty": "RSA", "alg": "RS256", "kid": "new", "n": "", "e": "AQAB"}]}"#;
+        provider.store_response(Ok(downloaded(rotated))).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                KeyEvent::RefreshFailed,
+                KeyEvent::KeysAdded(vec!["new".to_owned()]),
+                KeyEvent::KeysRemoved(vec!["known".to_owned()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_status() {
+        let provider = GoogleKeyProvider::default();
+        assert_eq!(provider.health(), KeyHealth::Unhealthy);
+        provider.store_response(Err(())).unwrap_err();
+        provider.store_response(Ok(downloaded("{}"))).unwrap_err();
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+        assert_eq!(provider.health(), KeyHealth::Healthy);
+
+        let status = provider.key_status();
+        assert_eq!(status.keys.len(), 1);
+        assert_eq!(status.keys[0].id, "known");
+        assert_eq!(status.keys[0].algorithm, Some(Algorithm::RS256));
+        assert!(status.expires_at.is_some() && status.last_success.is_some());
+        assert_eq!(
+            status.last_error.map(|error| error.reason),
+            Some(FetchFailure::InvalidKeySet)
+        );
+        assert_eq!((status.successful_fetches, status.failed_fetches), (1, 2));
+
+        let expired = GoogleKeyProvider::default();
+        expired.load_key_set(JWKS, SystemTime::now()).unwrap();
+        assert_eq!(expired.health(), KeyHealth::Degraded);
+    }
+
+    pub(super) fn unreachable_provider() -> GoogleKeyProvider {
+        GoogleKeyProvider {
+            url: "http://127.0.0.1:9/certs".to_owned(),
+            ..GoogleKeyProvider::default()
+        }
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_degraded_provider_serves_expired_keys() {
+        use super::KeyProvider;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let provider = unreachable_provider()
+            .on_key_event(move |event| recorded.lock().unwrap().push(event.clone()));
+        provider.load_key_set(JWKS, SystemTime::now()).unwrap();
+        assert!(provider.health().is_ready());
+        assert!(provider.get_key("known").unwrap().is_some());
+        // Within the cooldown the expired keys are used without another download.
+        assert!(provider.get_key("known").unwrap().is_some());
+        assert!(provider.get_key("unknown").unwrap().is_none());
+        assert_eq!(
+            events.lock().unwrap()[..2],
+            [
+                KeyEvent::RefreshFailed,
+                KeyEvent::StaleKeyUsed("known".to_owned())
+            ]
+        );
+        assert_eq!(provider.key_status().failed_fetches, 1);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_keys_past_max_stale_are_not_used() {
+        use super::KeyProvider;
+
+        // A key set that expired long ago, left in a shared store while Google is unreachable.
+        let store = MemoryKeyCacheStore::new();
+        let expired_at = SystemTime::now() - Duration::from_secs(14 * 24 * 3600);
+        store.put(
+            super::GOOGLE_CERT_URL,
+            &StoredKeySet {
+                key_set: JWKS.to_owned(),
+                expires_at: expired_at,
+            },
+        );
+        let provider = GoogleKeyProvider::default().with_cache_store(store);
+        assert_eq!(provider.health(), KeyHealth::Unhealthy);
+        assert_eq!(provider.key_status().expires_at, Some(expired_at));
+
+        let provider = unreachable_provider();
+        provider
+            .load_key_set(JWKS, SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+        assert_eq!(provider.health(), KeyHealth::Unhealthy);
+        assert!(provider.get_key("known").is_err());
+        // Within the cooldown there is no download, and the keys are still not used.
+        assert!(provider.get_key("known").is_err());
+        assert_eq!(provider.key_status().failed_fetches, 1);
+
+        let provider = unreachable_provider().with_max_stale(Duration::from_secs(3 * 3600));
+        provider
+            .load_key_set(JWKS, SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+        assert_eq!(provider.health(), KeyHealth::Degraded);
+        assert!(provider.get_key("known").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_key_archive() {
+        let archive = Arc::new(MemoryKeyArchive::new());
+        let provider = GoogleKeyProvider::default().with_key_archive(archive.clone());
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+        let rotated =
+            r#"{"keys": [{"kty": "RSA", "alg": "RS256", "kid": "new", "n": "", "e": "AQAB"}]}"#;
+        provider.store_response(Ok(downloaded(rotated))).unwrap();
+        assert!(provider.get_cached_key("known").unwrap().is_none());
+
+        let archived = archive.get("known").unwrap();
+        assert!(archived.first_seen <= archived.last_seen);
+        assert!(archived.was_current_at(archived.last_seen + Duration::from_secs(3000)));
+        assert!(!archived.was_current_at(archived.first_seen - Duration::from_secs(1)));
+        assert!(archive.get("new").is_some());
+    }
+
+    #[test]
+    fn test_store_can_use_provider() {
+        use std::sync::Weak;
+
+        #[derive(Default)]
+        struct ReentrantStore {
+            provider: Mutex<Weak<GoogleKeyProvider>>,
+        }
+        impl KeyCacheStore for ReentrantStore {
+            fn get(&self, _source: &str) -> Option<StoredKeySet> {
+                None
+            }
+            fn put(&self, _source: &str, _key_set: &StoredKeySet) {
+                let provider = self.provider.lock().unwrap().upgrade().unwrap();
+                assert_eq!(provider.key_status().keys.len(), 1);
+            }
+        }
+
+        let store = Arc::new(ReentrantStore::default());
+        let provider = Arc::new(GoogleKeyProvider::default().with_cache_store(store.clone()));
+        *store.provider.lock().unwrap() = Arc::downgrade(&provider);
+        provider.store_response(Ok(downloaded(JWKS))).unwrap();
+    }
+
+    #[test]
+    fn test_key_policy_rejects_weak_keys() {
+        use crate::key_policy::KeyPolicy;
+        use openssl::rsa::Rsa;
+
+        let rsa = Rsa::generate(1024).unwrap();
+        let weak = serde_json::json!({"keys": [{
+            "kty": "RSA",
+            "alg": "RS256",
+            "kid": "weak",
+            "n": crate::base64_encode(&rsa.n().to_vec()),
+            "e": crate::base64_encode(&rsa.e().to_vec()),
+        }]});
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let provider = GoogleKeyProvider::default()
+            .with_key_policy(KeyPolicy::new())
+            .on_key_event(move |event| recorded.lock().unwrap().push(event.clone()));
+        provider
+            .store_response(Ok(downloaded(&weak.to_string())))
+            .unwrap();
+        assert!(provider.get_cached_key("weak").unwrap().is_none());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![KeyEvent::KeysRejected(vec!["weak".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn test_refresh_delay() {
+        let provider = GoogleKeyProvider::default();
+        assert_eq!(provider.refresh_delay(), Duration::from_secs(0));
+        provider.lock_fetch_state().last_fetch = Some(Instant::now());
+        assert!(provider.refresh_delay() > Duration::from_secs(5));
+
+        let provider = cached_provider(Duration::from_secs(60));
+        let delay = provider.refresh_delay();
+        assert!(delay > Duration::from_secs(3000) && delay <= Duration::from_secs(3540));
+    }
+}
+
 #[cfg(all(test, feature = "async"))]
 mod async_test {
+    use super::cache_test::{downloaded, unreachable_provider};
     use super::{AsyncKeyProvider, GoogleKeyProvider};
-    use tokio;
+    use futures::future::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+
+    const JWKS: &str =
+        r#"{"keys": [{"kty": "RSA", "alg": "RS256", "kid": "known", "n": "", "e": "AQAB"}]}"#;
+
+    #[tokio::test]
+    async fn test_single_flight_download() {
+        let provider = GoogleKeyProvider::default();
+        let downloads = AtomicUsize::new(0);
+        let fetch = || {
+            downloads.fetch_add(1, Ordering::SeqCst);
+            async {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                Ok(downloaded(JWKS))
+            }
+            .boxed()
+        };
+        let (first, second) = futures::join!(
+            provider.get_key_shared("known", fetch),
+            provider.get_key_shared("known", fetch)
+        );
+        assert!(first.unwrap().is_some());
+        assert!(second.unwrap().is_some());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert!(provider.in_flight.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_degraded_provider_serves_expired_keys_async() {
+        let provider = unreachable_provider();
+        provider.load_key_set(JWKS, SystemTime::now()).unwrap();
+        assert!(provider.health().is_ready());
+        assert!(provider.get_key_async("known").await.unwrap().is_some());
+        assert!(provider.get_key_async("known").await.unwrap().is_some());
+        assert!(provider.get_key_async("unknown").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keys_past_max_stale_are_not_used_async() {
+        let provider = unreachable_provider();
+        provider
+            .load_key_set(JWKS, SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+        assert!(!provider.health().is_ready());
+        assert!(provider.get_key_async("known").await.is_err());
+        assert!(provider.get_key_async("known").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cache_store_runs_off_the_executor() {
+        use crate::key_cache_store::{KeyCacheStore, MemoryKeyCacheStore, StoredKeySet};
+        use std::sync::{Arc, Mutex};
+        use std::thread::{self, ThreadId};
+
+        #[derive(Default)]
+        struct ThreadStore {
+            inner: MemoryKeyCacheStore,
+            threads: Mutex<Vec<ThreadId>>,
+        }
+        impl KeyCacheStore for ThreadStore {
+            fn get(&self, source: &str) -> Option<StoredKeySet> {
+                self.threads.lock().unwrap().push(thread::current().id());
+                self.inner.get(source)
+            }
+            fn put(&self, source: &str, key_set: &StoredKeySet) {
+                self.threads.lock().unwrap().push(thread::current().id());
+                self.inner.put(source, key_set)
+            }
+        }
+
+        let store = Arc::new(ThreadStore::default());
+        let provider = GoogleKeyProvider::default().with_cache_store(store.clone());
+        store.threads.lock().unwrap().clear();
+        store.inner.put(
+            super::GOOGLE_CERT_URL,
+            &StoredKeySet {
+                key_set: JWKS.to_owned(),
+                expires_at: SystemTime::now() + Duration::from_secs(3600),
+            },
+        );
+        assert!(provider.get_key_async("known").await.unwrap().is_some());
+        provider
+            .download_keys_async(|| futures::future::ready(Ok(downloaded(JWKS))).boxed())
+            .await
+            .unwrap();
+
+        let threads = store.threads.lock().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert!(threads.iter().all(|&id| id != thread::current().id()));
+    }
+
     #[tokio::test]
     async fn test_google_provider_async() {
-        let mut provider = GoogleKeyProvider::default();
+        let provider = GoogleKeyProvider::default();
         assert!(provider.get_key_async("test").await.is_ok());
         assert!(provider.get_key_async("test").await.is_ok());
     }
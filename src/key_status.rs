@@ -0,0 +1,50 @@
+use crate::algorithm::Algorithm;
+use std::time::SystemTime;
+
+/// A snapshot of the keys held by a `GoogleKeyProvider`, see `GoogleKeyProvider::key_status`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyStatus {
+    pub keys: Vec<KeyInfo>,
+    pub expires_at: Option<SystemTime>,
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<FetchError>,
+    pub successful_fetches: u64,
+    pub failed_fetches: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyInfo {
+    pub id: String,
+    pub algorithm: Option<Algorithm>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchError {
+    pub time: SystemTime,
+    pub reason: FetchFailure,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchFailure {
+    /// The keys could not be downloaded.
+    Download,
+    /// The response was not a key set in the expected format.
+    InvalidKeySet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyHealth {
+    /// The cached keys have not expired.
+    Healthy,
+    /// Tokens can still be verified, but only against expired keys or the fallback key set.
+    Degraded,
+    /// There are no keys to verify tokens with, or the keys expired too long ago to be used.
+    Unhealthy,
+}
+
+impl KeyHealth {
+    /// Whether tokens can be verified at all, for use in readiness checks.
+    pub fn is_ready(self) -> bool {
+        self != KeyHealth::Unhealthy
+    }
+}
@@ -2,18 +2,54 @@
 mod test;
 
 mod algorithm;
+mod certificate_chain;
 mod client;
+mod composite_key_provider;
+mod diagnose;
 mod error;
 mod header;
+mod inspect;
 mod jwk;
+mod key_archive;
+mod key_cache_store;
+mod key_event;
+mod key_policy;
 mod key_provider;
+mod key_status;
+mod refresher;
+mod static_key_provider;
 mod token;
 mod unverified_token;
+mod x509;
 
-pub use crate::client::Client;
-pub use crate::token::{IdPayload, RequiredClaims, Token};
+pub use crate::algorithm::Algorithm;
+pub use crate::certificate_chain::CertificateChainPolicy;
+pub use crate::client::{Client, GenericClient, GenericClientBuilder};
+pub use crate::composite_key_provider::{CompositeKeyProvider, SourceKeyProvider};
+pub use crate::diagnose::{Check, CheckOutcome, CheckResult, DiagnosticReport};
+pub use crate::header::Header;
+pub use crate::inspect::{decode_unverified, UnverifiedContents};
+pub use crate::jwk::{JsonWebKey, JsonWebKeySet, KeyType, KeyUse};
+pub use crate::key_archive::{ArchivedKey, FileKeyArchive, KeyArchive, MemoryKeyArchive};
+pub use crate::key_cache_store::{
+    FileKeyCacheStore, KeyCacheStore, MemoryKeyCacheStore, StoredKeySet,
+};
+pub use crate::key_event::KeyEvent;
+pub use crate::key_policy::KeyPolicy;
+#[cfg(feature = "async")]
+pub use crate::key_provider::AsyncKeyProvider;
+pub use crate::key_provider::GoogleKeyProvider;
+#[cfg(feature = "blocking")]
+pub use crate::key_provider::KeyProvider;
+pub use crate::key_status::{FetchError, FetchFailure, KeyHealth, KeyInfo, KeyStatus};
+pub use crate::static_key_provider::{FileKeyProvider, StaticKeyProvider};
+pub use crate::token::{Address, BorrowedToken, IdPayload, RequiredClaims, StandardClaims, Token};
 pub use error::Error;
 
 fn base64_decode(input: &str) -> Result<Vec<u8>, base64::DecodeError> {
     base64::decode_config(&input, base64::URL_SAFE)
 }
+
+fn base64_encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
@@ -0,0 +1,40 @@
+use crate::key_provider::GoogleKeyProvider;
+use std::sync::Weak;
+
+#[cfg(feature = "blocking")]
+pub fn spawn_refresher(provider: Weak<GoogleKeyProvider>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || loop {
+        let delay = match provider.upgrade() {
+            Some(provider) => provider.refresh_delay(),
+            None => return,
+        };
+        std::thread::sleep(delay);
+        match provider.upgrade() {
+            Some(provider) => {
+                let _ = provider.download_keys();
+            }
+            None => return,
+        }
+    })
+}
+
+#[cfg(feature = "async")]
+pub fn spawn_refresher_async(provider: Weak<GoogleKeyProvider>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let delay = match provider.upgrade() {
+                Some(provider) => provider.refresh_delay(),
+                None => return,
+            };
+            tokio::time::delay_for(delay).await;
+            match provider.upgrade() {
+                Some(provider) => {
+                    let _ = provider
+                        .download_keys_async(|| provider.fetch_async())
+                        .await;
+                }
+                None => return,
+            }
+        }
+    })
+}
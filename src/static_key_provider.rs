@@ -0,0 +1,173 @@
+use crate::error::Error;
+use crate::jwk::{JsonWebKey, JsonWebKeySet};
+use crate::key_policy::KeyPolicy;
+#[cfg(feature = "async")]
+use crate::key_provider::AsyncKeyProvider;
+#[cfg(feature = "blocking")]
+use crate::key_provider::KeyProvider;
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serves a fixed key set, e.g. in air-gapped environments or tests.
+pub struct StaticKeyProvider {
+    key_set: JsonWebKeySet,
+}
+
+impl StaticKeyProvider {
+    pub fn new(mut key_set: JsonWebKeySet) -> Self {
+        key_set.prepare_keys();
+        Self { key_set }
+    }
+
+    /// Leaves out the keys that do not meet `policy`.
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        self.key_set.apply_policy(&policy);
+        self
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl KeyProvider for StaticKeyProvider {
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Ok(self.key_set.get_key(key_id))
+    }
+    fn get_key_ids(&self) -> Vec<String> {
+        self.key_set.get_key_ids()
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncKeyProvider for StaticKeyProvider {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Ok(self.key_set.get_key(key_id))
+    }
+    async fn get_key_ids_async(&self) -> Vec<String> {
+        self.key_set.get_key_ids()
+    }
+}
+
+/// Serves the key set stored in a JWKS file, and reloads it when the file changes. The file is
+/// checked for changes on lookups, at most once per check interval.
+pub struct FileKeyProvider {
+    path: PathBuf,
+    check_interval: Duration,
+    key_policy: Option<KeyPolicy>,
+    key_set: RwLock<JsonWebKeySet>,
+    last_check: Mutex<FileCheck>,
+}
+
+struct FileCheck {
+    checked_at: Instant,
+    version: Option<FileVersion>,
+}
+
+#[derive(PartialEq)]
+struct FileVersion {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileKeyProvider {
+    /// Loads the key set from `path`, failing with `Error::RetrieveKeyFailure` if it cannot be
+    /// read or parsed.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let version = file_version(&path);
+        let key_set = load_key_set(&path, None).ok_or(Error::RetrieveKeyFailure)?;
+        Ok(Self {
+            path,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            key_policy: None,
+            key_set: RwLock::new(key_set),
+            last_check: Mutex::new(FileCheck {
+                checked_at: Instant::now(),
+                version,
+            }),
+        })
+    }
+
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Leaves the keys that do not meet `policy` out of the current and reloaded key sets.
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        self.key_set.get_mut().unwrap().apply_policy(&policy);
+        self.key_policy = Some(policy);
+        self
+    }
+
+    /// Reloads the file if it changed since the last check. A file that cannot be parsed is
+    /// ignored, and the previous keys stay in use.
+    fn reload_if_changed(&self) {
+        let mut last_check = self.last_check.lock().unwrap();
+        if last_check.checked_at.elapsed() < self.check_interval {
+            return;
+        }
+        last_check.checked_at = Instant::now();
+        let version = file_version(&self.path);
+        if version == last_check.version {
+            return;
+        }
+        if let Some(key_set) = load_key_set(&self.path, self.key_policy.as_ref()) {
+            *self.key_set.write().unwrap() = key_set;
+            last_check.version = version;
+        }
+    }
+
+    fn lookup(&self, key_id: &str) -> Option<JsonWebKey> {
+        self.reload_if_changed();
+        self.key_set.read().unwrap().get_key(key_id)
+    }
+
+    fn key_ids(&self) -> Vec<String> {
+        self.reload_if_changed();
+        self.key_set.read().unwrap().get_key_ids()
+    }
+}
+
+fn file_version(path: &Path) -> Option<FileVersion> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileVersion {
+        modified: metadata.modified().ok()?,
+        len: metadata.len(),
+    })
+}
+
+fn load_key_set(path: &Path, policy: Option<&KeyPolicy>) -> Option<JsonWebKeySet> {
+    let mut key_set: JsonWebKeySet = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    if let Some(policy) = policy {
+        key_set.apply_policy(policy);
+    }
+    key_set.prepare_keys();
+    Some(key_set)
+}
+
+#[cfg(feature = "blocking")]
+impl KeyProvider for FileKeyProvider {
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Ok(self.lookup(key_id))
+    }
+    fn get_key_ids(&self) -> Vec<String> {
+        self.key_ids()
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncKeyProvider for FileKeyProvider {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Ok(self.lookup(key_id))
+    }
+    async fn get_key_ids_async(&self) -> Vec<String> {
+        self.key_ids()
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use crate::algorithm::Algorithm;
 use crate::error::Error;
 use crate::jwk::JsonWebKey;
 use crate::jwk::JsonWebKeySet;
@@ -10,8 +11,8 @@ use crate::key_provider::KeyProvider;
 #[cfg(feature = "async")]
 use async_trait::async_trait;
 
-const TOKEN: &'static str = "eyJhbGciOiJSUzI1NiIsImtpZCI6ImE3NDhlOWY3NjcxNTlmNjY3YTAyMjMzMThkZTBiMjMyOWU1NDQzNjIifQ.eyJhenAiOiIzNzc3MjExNzQwOC1xanFvOWhjYTUxM3BkY3VudW10N2drMDhpaTZ0ZThpcy5hcHBzLmdvb2dsZXVzZXJjb250ZW50LmNvbSIsImF1ZCI6IjM3NzcyMTE3NDA4LXFqcW85aGNhNTEzcGRjdW51bXQ3Z2swOGlpNnRlOGlzLmFwcHMuZ29vZ2xldXNlcmNvbnRlbnQuY29tIiwic3ViIjoiMTA3MDY3MzYxNTAzOTU0NDc0NDg4IiwiZW1haWwiOiJmdWNoc25qQGdtYWlsLmNvbSIsImVtYWlsX3ZlcmlmaWVkIjp0cnVlLCJhdF9oYXNoIjoiaTBOWk5kYWp3UklJbDJvUk9zUUptUSIsImV4cCI6MTUyNjQ5MjUzMywiaXNzIjoiYWNjb3VudHMuZ29vZ2xlLmNvbSIsImp0aSI6IjNmMjc1YjRiY2JmZDU0Y2IxNjZmMzcxNWQ1NTBkMWNmMmUxYThiZGEiLCJpYXQiOjE1MjY0ODg5MzMsIm5hbWUiOiJOYXRoYW4gRm94IiwicGljdHVyZSI6Imh0dHBzOi8vbGg1Lmdvb2dsZXVzZXJjb250ZW50LmNvbS8tbEJSLWE3Z2gwdFkvQUFBQUFBQUFBQUkvQUFBQUFBQUFFUk0vNDFHUk43cDNNVzQvczk2LWMvcGhvdG8uanBnIiwiZ2l2ZW5fbmFtZSI6Ik5hdGhhbiIsImZhbWlseV9uYW1lIjoiRm94IiwibG9jYWxlIjoiZW4ifQ.pOoIMLZgZIFP-fgQirCRRK31ap_CO7WZDeHge-U5GoAvF0VdkoSDSL-1-8d93qKb8IWzi2iS2MgaLekcX8eELM5x39Th1sBwjQGjYr5AXmqE53WDQiqvKzrz-BZ3ay0uSAMllxWfFi62BkSP3m1HJNWyUWrUf6GyI-Vy024dtrX9Qq_BOznJWbQVhHf5aA7x5AAoLHZ_PmzxbUlDQ7Go6FD7sgkoksZI4Cp77HZJMXXGVOrvvXJkpctTcuBZ2P-2filLmb29JIm0e4McOjeHQTV7XNGdzTZoyeSZcU5xTVFQK89e-SIPHKyaL7TAr_faBbTGzVryYfa2VFyKi7Z9gA";
-const JWKS: &'static str = r#"{
+const TOKEN: &str = "eyJhbGciOiJSUzI1NiIsImtpZCI6ImE3NDhlOWY3NjcxNTlmNjY3YTAyMjMzMThkZTBiMjMyOWU1NDQzNjIifQ.eyJhenAiOiIzNzc3MjExNzQwOC1xanFvOWhjYTUxM3BkY3VudW10N2drMDhpaTZ0ZThpcy5hcHBzLmdvb2dsZXVzZXJjb250ZW50LmNvbSIsImF1ZCI6IjM3NzcyMTE3NDA4LXFqcW85aGNhNTEzcGRjdW51bXQ3Z2swOGlpNnRlOGlzLmFwcHMuZ29vZ2xldXNlcmNvbnRlbnQuY29tIiwic3ViIjoiMTA3MDY3MzYxNTAzOTU0NDc0NDg4IiwiZW1haWwiOiJmdWNoc25qQGdtYWlsLmNvbSIsImVtYWlsX3ZlcmlmaWVkIjp0cnVlLCJhdF9oYXNoIjoiaTBOWk5kYWp3UklJbDJvUk9zUUptUSIsImV4cCI6MTUyNjQ5MjUzMywiaXNzIjoiYWNjb3VudHMuZ29vZ2xlLmNvbSIsImp0aSI6IjNmMjc1YjRiY2JmZDU0Y2IxNjZmMzcxNWQ1NTBkMWNmMmUxYThiZGEiLCJpYXQiOjE1MjY0ODg5MzMsIm5hbWUiOiJOYXRoYW4gRm94IiwicGljdHVyZSI6Imh0dHBzOi8vbGg1Lmdvb2dsZXVzZXJjb250ZW50LmNvbS8tbEJSLWE3Z2gwdFkvQUFBQUFBQUFBQUkvQUFBQUFBQUFFUk0vNDFHUk43cDNNVzQvczk2LWMvcGhvdG8uanBnIiwiZ2l2ZW5fbmFtZSI6Ik5hdGhhbiIsImZhbWlseV9uYW1lIjoiRm94IiwibG9jYWxlIjoiZW4ifQ.pOoIMLZgZIFP-fgQirCRRK31ap_CO7WZDeHge-U5GoAvF0VdkoSDSL-1-8d93qKb8IWzi2iS2MgaLekcX8eELM5x39Th1sBwjQGjYr5AXmqE53WDQiqvKzrz-BZ3ay0uSAMllxWfFi62BkSP3m1HJNWyUWrUf6GyI-Vy024dtrX9Qq_BOznJWbQVhHf5aA7x5AAoLHZ_PmzxbUlDQ7Go6FD7sgkoksZI4Cp77HZJMXXGVOrvvXJkpctTcuBZ2P-2filLmb29JIm0e4McOjeHQTV7XNGdzTZoyeSZcU5xTVFQK89e-SIPHKyaL7TAr_faBbTGzVryYfa2VFyKi7Z9gA";
+const JWKS: &str = r#"{
  "keys": [
   {
    "kty": "RSA",
@@ -31,14 +32,13 @@ const JWKS: &'static str = r#"{
   }
  ]
 }"#;
-const AUDIENCE: &'static str =
-    "37772117408-qjqo9hca513pdcunumt7gk08ii6te8is.apps.googleusercontent.com";
+const AUDIENCE: &str = "37772117408-qjqo9hca513pdcunumt7gk08ii6te8is.apps.googleusercontent.com";
 
 struct TestKeyProvider;
 
 #[cfg(feature = "blocking")]
 impl KeyProvider for TestKeyProvider {
-    fn get_key(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+    fn get_key(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
         let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
         Ok(set.get_key(key_id))
     }
@@ -47,12 +47,35 @@ impl KeyProvider for TestKeyProvider {
 #[cfg(feature = "async")]
 #[async_trait]
 impl AsyncKeyProvider for TestKeyProvider {
-    async fn get_key_async(&mut self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+    async fn get_key_async(&self, key_id: &str) -> Result<Option<JsonWebKey>, ()> {
         let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
         Ok(set.get_key(key_id))
     }
 }
 
+struct UnreachableKeyProvider;
+
+#[cfg(feature = "blocking")]
+impl KeyProvider for UnreachableKeyProvider {
+    fn get_key(&self, _key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Err(())
+    }
+    fn prefetch_keys(&self) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncKeyProvider for UnreachableKeyProvider {
+    async fn get_key_async(&self, _key_id: &str) -> Result<Option<JsonWebKey>, ()> {
+        Err(())
+    }
+    async fn prefetch_keys_async(&self) -> Result<(), ()> {
+        Err(())
+    }
+}
+
 #[cfg(feature = "blocking")]
 #[test]
 pub fn decode_keys() {
@@ -64,6 +87,22 @@ pub fn decode_keys() {
         .unwrap();
 }
 
+#[test]
+pub fn test_prepared_key() {
+    let mut set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    set.prepare_keys();
+    let key = set
+        .get_key("a748e9f767159f667a0223318de0b2329e544362")
+        .unwrap();
+    let (body, signature) = TOKEN.split_at(TOKEN.rfind('.').unwrap());
+    let signature = base64_decode(&signature[1..]).unwrap();
+    assert_eq!(key.verify(body.as_bytes(), &signature), Ok(()));
+    assert_eq!(
+        key.verify(b"tampered", &signature),
+        Err(Error::InvalidToken)
+    );
+}
+
 #[cfg(feature = "blocking")]
 #[test]
 pub fn test_client() {
@@ -99,6 +138,146 @@ pub fn test_id_token() {
     assert_eq!(id_token.get_payload().get_email(), "fuchsnj@gmail.com");
 }
 
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_tampered_signature() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .unsafe_ignore_expiration()
+        .build();
+    let (body, signature) = TOKEN.split_at(TOKEN.rfind('.').unwrap());
+    let mut signature = base64::decode_config(&signature[1..], base64::URL_SAFE).unwrap();
+    signature[0] ^= 1;
+    let tampered = format!(
+        "{}.{}",
+        body,
+        base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)
+    );
+    let result = client.verify_id_token(&tampered).map(|_| ());
+    assert_eq!(result, Err(Error::InvalidToken));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_verify_token_ignores_payload() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .unsafe_ignore_expiration()
+        .build();
+    let token = client.verify_token(TOKEN).expect("token should be valid");
+    assert_eq!(token.get_claims().get_audience(), AUDIENCE);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_static_key_provider() {
+    let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(StaticKeyProvider::new(set))
+        .unsafe_ignore_expiration()
+        .build();
+    let id_token = client
+        .verify_id_token(TOKEN)
+        .expect("id token should be valid");
+    assert_eq!(id_token.get_payload().get_email(), "fuchsnj@gmail.com");
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_file_key_provider_reload() {
+    let path = std::env::temp_dir().join(format!("jwks-reload-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"keys": []}"#).unwrap();
+    let provider = FileKeyProvider::new(&path)
+        .unwrap()
+        .with_check_interval(std::time::Duration::from_secs(0));
+    assert_eq!(
+        provider
+            .get_key("a748e9f767159f667a0223318de0b2329e544362")
+            .map(|key| key.is_some()),
+        Ok(false)
+    );
+    std::fs::write(&path, JWKS).unwrap();
+    let reloaded = provider.get_key("a748e9f767159f667a0223318de0b2329e544362");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reloaded.map(|key| key.is_some()), Ok(true));
+    assert!(FileKeyProvider::new(&path).is_err());
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_composite_key_provider() {
+    let google_keys: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let internal_keys: JsonWebKeySet = serde_json::from_str(r#"{"keys": []}"#).unwrap();
+    let provider = CompositeKeyProvider::new()
+        .with_provider(UnreachableKeyProvider)
+        .with_bound_provider(
+            StaticKeyProvider::new(internal_keys),
+            &["https://issuer.internal"],
+        )
+        .with_bound_provider(
+            StaticKeyProvider::new(google_keys.clone()),
+            &["accounts.google.com"],
+        );
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(provider)
+        .add_issuer("https://issuer.internal")
+        .unsafe_ignore_expiration()
+        .build();
+    assert!(client.verify_id_token(TOKEN).is_ok());
+
+    let misbound = CompositeKeyProvider::new().with_bound_provider(
+        StaticKeyProvider::new(google_keys.clone()),
+        &["https://issuer.internal"],
+    );
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(misbound)
+        .add_issuer("https://issuer.internal")
+        .unsafe_ignore_expiration()
+        .build();
+    assert_eq!(
+        client.verify_id_token(TOKEN).map(|_| ()),
+        Err(Error::InvalidToken)
+    );
+
+    // An outer binding narrows the inner one and never widens it.
+    let inner = CompositeKeyProvider::new().with_bound_provider(
+        StaticKeyProvider::new(google_keys),
+        &["https://issuer.internal"],
+    );
+    let nested = CompositeKeyProvider::new()
+        .with_bound_provider(inner, &["https://issuer.internal", "accounts.google.com"]);
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(nested)
+        .unsafe_ignore_expiration()
+        .build();
+    assert_eq!(
+        client.verify_id_token(TOKEN).map(|_| ()),
+        Err(Error::InvalidToken)
+    );
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_prefetch_keys() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .build();
+    assert_eq!(client.prefetch_keys(), Ok(()));
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(UnreachableKeyProvider)
+        .build();
+    assert_eq!(client.prefetch_keys(), Err(Error::RetrieveKeyFailure));
+}
+
+#[cfg(feature = "async")]
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+pub fn test_client_is_shareable() {
+    fn assert_shareable<T: Clone + Send + Sync>(_: &T) {}
+    assert_shareable(&Client::new(AUDIENCE));
+}
+
 #[cfg(feature = "async")]
 #[tokio::test]
 async fn decode_keys_async() {
@@ -142,11 +321,544 @@ async fn test_id_token_async() {
         .custom_key_provider(TestKeyProvider)
         .unsafe_ignore_expiration()
         .build();
-    let id_token = client
-        .verify_id_token_async(TOKEN)
-        .await
-        .expect("id token should be valid");
+    let verification = client.verify_id_token_async(TOKEN);
+    assert_send(&verification);
+    let id_token = verification.await.expect("id token should be valid");
     assert_eq!(id_token.get_claims().get_audience(), AUDIENCE);
     assert_eq!(id_token.get_payload().get_domain(), None);
     assert_eq!(id_token.get_payload().get_email(), "fuchsnj@gmail.com");
 }
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_prefetch_keys_async() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(UnreachableKeyProvider)
+        .build();
+    assert_eq!(
+        client.prefetch_keys_async().await,
+        Err(Error::RetrieveKeyFailure)
+    );
+}
+
+#[test]
+pub fn test_verify_archived_token() {
+    use std::time::{Duration, UNIX_EPOCH};
+    let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let key = set
+        .get_key("a748e9f767159f667a0223318de0b2329e544362")
+        .unwrap();
+    let issued_at = UNIX_EPOCH + Duration::from_secs(1526488933);
+    let path = std::env::temp_dir().join(format!("jwks-archive-{}.json", std::process::id()));
+    let archive = FileKeyArchive::new(&path);
+    archive.put(&ArchivedKey {
+        key,
+        first_seen: issued_at - Duration::from_secs(3600),
+        last_seen: issued_at,
+        valid_until: issued_at + Duration::from_secs(3600),
+    });
+    let client: Client = Client::new(AUDIENCE);
+    let id_token = client.verify_archived_id_token(TOKEN, &archive);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        id_token.map(|token| token.get_payload().get_email()),
+        Ok("fuchsnj@gmail.com".to_owned())
+    );
+
+    let archive = MemoryKeyArchive::new();
+    assert_eq!(
+        client.verify_archived_id_token(TOKEN, &archive).map(|_| ()),
+        Err(Error::InvalidToken)
+    );
+}
+
+#[test]
+pub fn test_key_set_skips_unusable_keys() {
+    let google_keys: serde_json::Value = serde_json::from_str(JWKS).unwrap();
+    let n = &google_keys["keys"][1]["n"];
+    let keys = serde_json::json!({"keys": [
+        {"kty": "RSA", "kid": "no-alg", "n": n, "e": "AQAB"},
+        {"kty": "RSA", "alg": "RS256", "use": "enc", "kid": "encryption", "n": n, "e": "AQAB"},
+        {"kty": "RSA", "alg": "RS256", "key_ops": ["encrypt"], "kid": "ops", "n": n, "e": "AQAB"},
+        {"kty": "RSA", "alg": "ES256", "kid": "mismatch", "n": n, "e": "AQAB"},
+        {"kty": "OKP", "alg": "EdDSA", "kid": "unknown", "crv": "Ed25519", "x": ""},
+    ]});
+    let set: JsonWebKeySet = serde_json::from_value(keys).unwrap();
+    assert_eq!(set.get_key_ids(), vec!["no-alg".to_owned()]);
+
+    let key = set.get_key("no-alg").unwrap();
+    let (body, signature) = TOKEN.split_at(TOKEN.rfind('.').unwrap());
+    let signature = base64_decode(&signature[1..]).unwrap();
+    assert_eq!(key.get_algorithm(), None);
+    assert_eq!(
+        key.verify(body.as_bytes(), &signature),
+        Err(Error::InvalidToken)
+    );
+    assert_eq!(
+        key.verify_with_algorithm(Algorithm::RS256, body.as_bytes(), &signature),
+        Ok(())
+    );
+    assert_eq!(
+        key.verify_with_algorithm(Algorithm::ES256, body.as_bytes(), &signature),
+        Err(Error::InvalidToken)
+    );
+}
+
+#[test]
+pub fn test_elliptic_curve_key() {
+    use openssl::bn::{BigNum, BigNumContext};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let private_key = EcKey::generate(&group).unwrap();
+    let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+    private_key
+        .public_key()
+        .affine_coordinates_gfp(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+        .unwrap();
+    let keys = serde_json::json!({"keys": [{
+        "kty": "EC",
+        "alg": "ES256",
+        "kid": "ec",
+        "crv": "P-256",
+        "x": base64_encode(&x.to_vec_padded(32).unwrap()),
+        "y": base64_encode(&y.to_vec_padded(32).unwrap()),
+    }]});
+    let set: JsonWebKeySet = serde_json::from_value(keys).unwrap();
+    let key = set.get_key("ec").unwrap();
+
+    let mut signer = Signer::new(
+        MessageDigest::sha256(),
+        &PKey::from_ec_key(private_key).unwrap(),
+    )
+    .unwrap();
+    signer.update(b"body").unwrap();
+    let der = EcdsaSig::from_der(&signer.sign_to_vec().unwrap()).unwrap();
+    let mut signature = der.r().to_vec_padded(32).unwrap();
+    signature.extend(der.s().to_vec_padded(32).unwrap());
+    assert_eq!(key.verify(b"body", &signature), Ok(()));
+    assert_eq!(
+        key.verify(b"tampered", &signature),
+        Err(Error::InvalidToken)
+    );
+}
+
+#[test]
+pub fn test_certificate_chain_thumbprint() {
+    use openssl::hash::{hash, MessageDigest};
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let rsa = Rsa::generate(2048).unwrap();
+    let certificate =
+        crate::x509::test::certificate(&PKey::from_rsa(rsa.clone()).unwrap(), 0, i32::MAX as i64)
+            .to_der()
+            .unwrap();
+    let thumbprint = base64_encode(&hash(MessageDigest::sha256(), &certificate).unwrap());
+    let key = |id: &str, thumbprint: &str| {
+        serde_json::json!({
+            "kty": "RSA",
+            "alg": "RS256",
+            "kid": id,
+            "n": base64_encode(&rsa.n().to_vec()),
+            "e": base64_encode(&rsa.e().to_vec()),
+            "x5c": [base64::encode(&certificate)],
+            "x5t#S256": thumbprint,
+        })
+    };
+    let keys = serde_json::json!({"keys": [key("good", &thumbprint), key("bad", "AAAA")]});
+    let set: JsonWebKeySet = serde_json::from_value(keys).unwrap();
+    assert_eq!(set.get_key_ids(), vec!["good".to_owned()]);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_certificate_chain_token() {
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::{X509NameBuilder, X509};
+
+    fn certificate(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                builder.set_issuer_name(issuer.subject_name()).unwrap();
+                builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+                builder.append_extension(constraints).unwrap();
+                builder.set_issuer_name(&name).unwrap();
+                builder.sign(key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        builder.build()
+    }
+
+    let root_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let root = certificate("root", &root_key, None);
+    let leaf_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let leaf = certificate("attest.android.com", &leaf_key, Some((&root, &root_key)));
+
+    let sign_with = |leaf: &X509, leaf_key: &PKey<Private>, issuer: &str| {
+        let header = serde_json::json!({
+            "alg": "RS256",
+            "x5c": [base64::encode(&leaf.to_der().unwrap()), base64::encode(&root.to_der().unwrap())],
+        });
+        let claims = serde_json::json!({
+            "iss": issuer,
+            "sub": "device",
+            "aud": AUDIENCE,
+            "azp": AUDIENCE,
+            "iat": 1526488933u64,
+            "exp": 4102444800u64,
+        });
+        let body = format!(
+            "{}.{}",
+            base64_encode(header.to_string().as_bytes()),
+            base64_encode(claims.to_string().as_bytes())
+        );
+        let mut signer = Signer::new(MessageDigest::sha256(), leaf_key).unwrap();
+        signer.update(body.as_bytes()).unwrap();
+        format!("{}.{}", body, base64_encode(&signer.sign_to_vec().unwrap()))
+    };
+    let sign = |issuer: &str| sign_with(&leaf, &leaf_key, issuer);
+    let token = sign("https://attest.example.com");
+
+    let verify_token = |policy: CertificateChainPolicy, token: &str| {
+        Client::builder(AUDIENCE)
+            .custom_key_provider(TestKeyProvider)
+            .add_issuer("https://attest.example.com")
+            .certificate_chain_policy(policy)
+            .build()
+            .verify_token_with_payload::<serde_json::Value>(token)
+            .map(|_| ())
+    };
+    let verify = |policy: CertificateChainPolicy| verify_token(policy, &token);
+    let trusted = CertificateChainPolicy::new()
+        .add_trust_anchor(root.clone())
+        .unwrap()
+        .add_issuer("https://attest.example.com");
+    assert_eq!(
+        verify(
+            trusted
+                .clone()
+                .with_subject_common_name("attest.android.com")
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        verify(trusted.clone().with_hostname("attest.android.com").unwrap()),
+        Ok(())
+    );
+    assert_eq!(
+        verify(
+            trusted
+                .clone()
+                .with_subject_common_name("other.example.com")
+        ),
+        Err(Error::InvalidToken)
+    );
+    assert_eq!(
+        verify(
+            CertificateChainPolicy::new()
+                .add_trust_anchor(leaf.clone())
+                .unwrap()
+        ),
+        Err(Error::InvalidToken)
+    );
+    // A trusted chain cannot sign for issuers the policy does not name, Google in particular.
+    assert_eq!(
+        verify(
+            CertificateChainPolicy::new()
+                .add_trust_anchor(root.clone())
+                .unwrap()
+        ),
+        Err(Error::InvalidToken)
+    );
+    assert_eq!(
+        verify_token(trusted.clone(), &sign("https://accounts.google.com")),
+        Err(Error::InvalidToken)
+    );
+
+    // The leaf key has to meet the key policy, like keys from the key provider.
+    let weak_key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+    let weak_leaf = certificate("attest.android.com", &weak_key, Some((&root, &root_key)));
+    let weak_token = sign_with(&weak_leaf, &weak_key, "https://attest.example.com");
+    assert_eq!(
+        verify_token(trusted.clone(), &weak_token),
+        Err(Error::InvalidToken)
+    );
+    // A hostname OpenSSL rejects is a configuration error, not a policy that trusts nothing.
+    assert_eq!(
+        trusted.clone().with_hostname("attest\0android.com").err(),
+        Some(Error::InvalidCertificate)
+    );
+    assert_eq!(
+        verify_token(
+            trusted.with_key_policy(KeyPolicy::new().with_min_rsa_bits(1024)),
+            &weak_token
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        CertificateChainPolicy::new()
+            .add_trust_anchor_pem(b"not a certificate")
+            .err(),
+        Some(Error::InvalidCertificate)
+    );
+    let anchor = CertificateChainPolicy::new().add_trust_anchor_pem(&root.to_pem().unwrap());
+    assert!(anchor.is_ok());
+}
+
+#[test]
+pub fn test_thumbprint_and_export() {
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+
+    // The example from RFC 7638, section 3.1.
+    let key: JsonWebKey = serde_json::from_value(serde_json::json!({
+        "kty": "RSA",
+        "kid": "2011-04-29",
+        "alg": "RS256",
+        "e": "AQAB",
+        "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+    }))
+    .unwrap();
+    assert_eq!(
+        key.thumbprint(MessageDigest::sha256()),
+        Ok("NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs".to_owned())
+    );
+
+    let public_key = key.public_key().unwrap();
+    let pem = key.to_pem().unwrap();
+    assert!(pem.starts_with("-----BEGIN PUBLIC KEY-----"));
+    assert!(PKey::public_key_from_pem(pem.as_bytes())
+        .unwrap()
+        .public_eq(&public_key));
+    assert!(PKey::public_key_from_der(&key.to_der().unwrap())
+        .unwrap()
+        .public_eq(&public_key));
+    assert!(key
+        .to_pkcs1_pem()
+        .unwrap()
+        .starts_with("-----BEGIN RSA PUBLIC KEY-----"));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_key_policy() {
+    let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let key_id = "a748e9f767159f667a0223318de0b2329e544362";
+    let provider = StaticKeyProvider::new(set.clone()).with_key_policy(KeyPolicy::new());
+    assert!(provider.get_key(key_id).unwrap().is_some());
+    let provider =
+        StaticKeyProvider::new(set).with_key_policy(KeyPolicy::new().with_min_rsa_bits(3072));
+    assert!(provider.get_key(key_id).unwrap().is_none());
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_serialize_token_and_key_set() {
+    let set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let round_trip: JsonWebKeySet =
+        serde_json::from_str(&serde_json::to_string(&set).unwrap()).unwrap();
+    assert_eq!(round_trip.get_key_ids(), set.get_key_ids());
+
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(StaticKeyProvider::new(round_trip))
+        .unsafe_ignore_expiration()
+        .build();
+    let id_token = client.verify_id_token(TOKEN).unwrap();
+    let stored = serde_json::to_string(&id_token).unwrap();
+    let restored: Token<IdPayload> = serde_json::from_str(&stored).unwrap();
+    assert_eq!(restored.get_claims(), id_token.get_claims());
+    assert_eq!(restored.get_payload(), id_token.get_payload());
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_token_source() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .unsafe_ignore_expiration()
+        .build();
+    let token = client.verify_token(TOKEN).expect("token should be valid");
+    assert_eq!(token.get_raw_token(), TOKEN);
+    assert_eq!(
+        token.get_key_id(),
+        Some("a748e9f767159f667a0223318de0b2329e544362")
+    );
+    assert_eq!(token.get_header().algorithm, Some(Algorithm::RS256));
+    assert_eq!(
+        token.get_raw_claims()["jti"],
+        "3f275b4bcbfd54cb166f3715d550d1cf2e1a8bda"
+    );
+    let stored = serde_json::to_string(&token).unwrap();
+    let restored: Token<()> = serde_json::from_str(&stored).unwrap();
+    assert_eq!(restored.get_raw_claims(), token.get_raw_claims());
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_standard_claims() {
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .unsafe_ignore_expiration()
+        .build();
+    let token = client
+        .verify_token_with_payload::<StandardClaims>(TOKEN)
+        .expect("token should be valid");
+    let claims = token.get_payload();
+    assert_eq!(claims.get_email(), Some("fuchsnj@gmail.com".to_owned()));
+    assert_eq!(claims.is_email_verified(), Some(true));
+    assert_eq!(
+        claims.get_access_token_hash(),
+        Some("i0NZNdajwRIIl2oROsQJmQ".to_owned())
+    );
+    assert_eq!(claims.get_nonce(), None);
+
+    let email_only: StandardClaims =
+        serde_json::from_str(r#"{"email": "a@example.com", "email_verified": "true"}"#).unwrap();
+    assert_eq!(email_only.is_email_verified(), Some(true));
+    assert_eq!(email_only.get_name(), None);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_borrowed_payload() {
+    #[derive(serde_derive::Deserialize)]
+    struct Profile<'a> {
+        email: &'a str,
+        given_name: &'a str,
+    }
+
+    let client = Client::builder(AUDIENCE)
+        .custom_key_provider(TestKeyProvider)
+        .unsafe_ignore_expiration()
+        .build();
+    let token = client
+        .verify_token_borrowed(TOKEN)
+        .expect("token should be valid");
+    let profile: Profile = token.get_payload().unwrap();
+    assert_eq!(profile.email, "fuchsnj@gmail.com");
+    assert_eq!(profile.given_name, "Nathan");
+    assert!(token
+        .get_payload_json()
+        .as_ptr_range()
+        .contains(&profile.email.as_ptr()));
+    assert_eq!(token.get_claims().get_audience(), AUDIENCE);
+}
+
+#[test]
+pub fn test_decode_unverified() {
+    let contents = decode_unverified(TOKEN).unwrap();
+    assert_eq!(
+        contents.get_header().unwrap().key_id.as_deref(),
+        Some("a748e9f767159f667a0223318de0b2329e544362")
+    );
+    assert_eq!(
+        contents.get_required_claims().unwrap().get_audience(),
+        AUDIENCE
+    );
+    assert_eq!(contents.get_claims_json()["exp"], 1526492533);
+    let payload: IdPayload = contents.get_payload().unwrap();
+    assert_eq!(payload.get_email(), "fuchsnj@gmail.com");
+    assert!(!contents.get_signature().is_empty());
+    assert!(decode_unverified("not a token").is_err());
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+pub fn test_diagnose() {
+    let key_set: JsonWebKeySet = serde_json::from_str(JWKS).unwrap();
+    let client = Client::builder("invalid client id")
+        .custom_key_provider(StaticKeyProvider::new(key_set))
+        .build();
+    let report = client.diagnose(TOKEN);
+    assert!(!report.is_valid());
+    let failures: Vec<Check> = report.failures().map(|result| result.check).collect();
+    assert_eq!(failures, vec![Check::Audience, Check::Expiration]);
+    let audience = report.get_check(Check::Audience).unwrap();
+    assert_eq!(audience.expected, "invalid client id");
+    assert_eq!(audience.actual, AUDIENCE);
+    assert_eq!(
+        report.get_check(Check::Expiration).unwrap().actual,
+        "1526492533"
+    );
+    assert_eq!(
+        report.get_check(Check::Signature).unwrap().outcome,
+        CheckOutcome::Passed
+    );
+    assert_eq!(report.available_key_ids.len(), 2);
+    assert!(report.to_string().contains("Audience Failed"));
+
+    let (body, _) = TOKEN.split_at(TOKEN.rfind('.').unwrap());
+    let report = client.diagnose(&format!("{}.AAAA", body));
+    assert_eq!(
+        report.get_check(Check::Signature).unwrap().outcome,
+        CheckOutcome::Failed
+    );
+
+    let report = client.diagnose("not a token");
+    let format = report.get_check(Check::Format).unwrap();
+    assert_eq!(format.outcome, CheckOutcome::Failed);
+    assert_eq!(
+        report.get_check(Check::KeyLookup).unwrap().outcome,
+        CheckOutcome::Skipped
+    );
+}
+
+#[test]
+pub fn test_file_cache_store_concurrent_writers() {
+    let dir = std::env::temp_dir().join(format!("google-jwks-writers-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("keys.json");
+    let writers: Vec<_> = (0..8)
+        .map(|_| {
+            let store = FileKeyCacheStore::new(&path);
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    let stored = StoredKeySet {
+                        key_set: JWKS.to_owned(),
+                        expires_at: std::time::SystemTime::now(),
+                    };
+                    store.put("source", &stored);
+                    assert_eq!(store.get("source").unwrap().key_set, JWKS);
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, 1);
+}
@@ -1,8 +1,59 @@
-use serde_derive::Deserialize;
+use crate::error::Error;
+use crate::header::Header;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::OnceLock;
 
+/// A verified token. Serializing it lets verified identity be stored or passed on without
+/// verifying again; only deserialize tokens from storage you trust, as nothing is re-checked.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Token<P> {
+    #[serde(rename = "claims")]
     required_claims: RequiredClaims,
     payload: P,
+    #[serde(default)]
+    header: Header,
+    #[serde(default)]
+    raw_claims: RawClaims,
+    #[serde(default)]
+    raw_token: String,
+}
+
+/// The decoded payload of a verified token, only parsed into JSON when it is asked for.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RawClaims {
+    json: Vec<u8>,
+    parsed: OnceLock<serde_json::Value>,
+}
+
+impl RawClaims {
+    pub(crate) fn new(json: Vec<u8>) -> Self {
+        Self {
+            json,
+            parsed: OnceLock::new(),
+        }
+    }
+    fn get(&self) -> &serde_json::Value {
+        self.parsed
+            .get_or_init(|| serde_json::from_slice(&self.json).unwrap_or_default())
+    }
+}
+
+impl Serialize for RawClaims {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawClaims {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let claims = RawClaims::default();
+        let _ = claims
+            .parsed
+            .set(serde_json::Value::deserialize(deserializer)?);
+        Ok(claims)
+    }
 }
 
 impl<P> Token<P> {
@@ -10,6 +61,29 @@ impl<P> Token<P> {
         Token {
             required_claims,
             payload,
+            header: Header::default(),
+            raw_claims: RawClaims::default(),
+            raw_token: String::new(),
+        }
+    }
+    pub(crate) fn with_source(
+        mut self,
+        header: Header,
+        raw_claims: RawClaims,
+        raw_token: String,
+    ) -> Self {
+        self.header = header;
+        self.raw_claims = raw_claims;
+        self.raw_token = raw_token;
+        self
+    }
+    pub(crate) fn map_payload<Q, F: FnOnce(P) -> Q>(self, f: F) -> Token<Q> {
+        Token {
+            required_claims: self.required_claims,
+            payload: f(self.payload),
+            header: self.header,
+            raw_claims: self.raw_claims,
+            raw_token: self.raw_token,
         }
     }
     pub fn get_claims(&self) -> RequiredClaims {
@@ -18,9 +92,58 @@ impl<P> Token<P> {
     pub fn get_payload(&self) -> &P {
         &self.payload
     }
+    pub fn get_header(&self) -> &Header {
+        &self.header
+    }
+    /// Every claim in the token, including those not in `RequiredClaims` or the payload type.
+    pub fn get_raw_claims(&self) -> &serde_json::Value {
+        self.raw_claims.get()
+    }
+    /// The token as it was verified, e.g. to forward it to another service.
+    pub fn get_raw_token(&self) -> &str {
+        &self.raw_token
+    }
+    /// The id of the key that signed the token. Tokens signed with an `x5c` certificate chain
+    /// may not have one.
+    pub fn get_key_id(&self) -> Option<&str> {
+        self.header.key_id.as_deref()
+    }
+}
+
+/// A verified token that keeps its payload as decoded JSON. The payload type is deserialized on
+/// demand and can borrow `&str` fields from the token, instead of allocating its own strings.
+#[derive(Clone, Debug)]
+pub struct BorrowedToken {
+    token: Token<Vec<u8>>,
+}
+
+impl BorrowedToken {
+    pub(crate) fn new(token: Token<Vec<u8>>) -> Self {
+        Self { token }
+    }
+    pub fn get_claims(&self) -> RequiredClaims {
+        self.token.get_claims()
+    }
+    pub fn get_header(&self) -> &Header {
+        self.token.get_header()
+    }
+    pub fn get_raw_token(&self) -> &str {
+        self.token.get_raw_token()
+    }
+    pub fn get_key_id(&self) -> Option<&str> {
+        self.token.get_key_id()
+    }
+    pub fn get_payload_json(&self) -> &[u8] {
+        &self.token.payload
+    }
+    /// Strings with escape sequences cannot be borrowed, use `Cow<str>` for fields that may
+    /// contain them.
+    pub fn get_payload<'a, P: Deserialize<'a>>(&'a self) -> Result<P, Error> {
+        Ok(serde_json::from_slice(&self.token.payload)?)
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct RequiredClaims {
     #[serde(rename = "iss")]
     issuer: String,
@@ -62,7 +185,7 @@ impl RequiredClaims {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct IdPayload {
     email: String,
     email_verified: bool,
@@ -71,6 +194,7 @@ pub struct IdPayload {
     given_name: String,
     family_name: String,
     locale: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     hd: Option<String>,
 }
 
@@ -100,3 +224,207 @@ impl IdPayload {
         self.hd.clone()
     }
 }
+
+/// The standard claims from OpenID Connect Core sections 2 and 5.1, plus Google's `hd`. Every
+/// claim is optional, as the claims in a token depend on the scopes that were requested.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StandardClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    family_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    middle_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preferred_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    picture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    website: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bool_or_string"
+    )]
+    email_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gender: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    birthdate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zoneinfo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    phone_number: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bool_or_string"
+    )]
+    phone_number_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<Address>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    at_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amr: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hd: Option<String>,
+}
+
+impl StandardClaims {
+    pub fn get_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    pub fn get_given_name(&self) -> Option<String> {
+        self.given_name.clone()
+    }
+    pub fn get_family_name(&self) -> Option<String> {
+        self.family_name.clone()
+    }
+    pub fn get_middle_name(&self) -> Option<String> {
+        self.middle_name.clone()
+    }
+    pub fn get_nickname(&self) -> Option<String> {
+        self.nickname.clone()
+    }
+    pub fn get_preferred_username(&self) -> Option<String> {
+        self.preferred_username.clone()
+    }
+    pub fn get_profile_url(&self) -> Option<String> {
+        self.profile.clone()
+    }
+    pub fn get_picture_url(&self) -> Option<String> {
+        self.picture.clone()
+    }
+    pub fn get_website(&self) -> Option<String> {
+        self.website.clone()
+    }
+    pub fn get_email(&self) -> Option<String> {
+        self.email.clone()
+    }
+    pub fn is_email_verified(&self) -> Option<bool> {
+        self.email_verified
+    }
+    pub fn get_gender(&self) -> Option<String> {
+        self.gender.clone()
+    }
+    pub fn get_birthdate(&self) -> Option<String> {
+        self.birthdate.clone()
+    }
+    pub fn get_zoneinfo(&self) -> Option<String> {
+        self.zoneinfo.clone()
+    }
+    pub fn get_locale(&self) -> Option<String> {
+        self.locale.clone()
+    }
+    pub fn get_phone_number(&self) -> Option<String> {
+        self.phone_number.clone()
+    }
+    pub fn is_phone_number_verified(&self) -> Option<bool> {
+        self.phone_number_verified
+    }
+    pub fn get_address(&self) -> Option<Address> {
+        self.address.clone()
+    }
+    pub fn get_updated_at(&self) -> Option<u64> {
+        self.updated_at
+    }
+    pub fn get_nonce(&self) -> Option<String> {
+        self.nonce.clone()
+    }
+    pub fn get_access_token_hash(&self) -> Option<String> {
+        self.at_hash.clone()
+    }
+    pub fn get_auth_time(&self) -> Option<u64> {
+        self.auth_time
+    }
+    pub fn get_token_id(&self) -> Option<String> {
+        self.jti.clone()
+    }
+    pub fn get_authentication_context(&self) -> Option<String> {
+        self.acr.clone()
+    }
+    pub fn get_authentication_methods(&self) -> Option<Vec<String>> {
+        self.amr.clone()
+    }
+    pub fn get_domain(&self) -> Option<String> {
+        self.hd.clone()
+    }
+}
+
+/// The `address` claim from OpenID Connect Core section 5.1.1.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Address {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    formatted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    street_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locality: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    postal_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+}
+
+impl Address {
+    pub fn get_formatted(&self) -> Option<String> {
+        self.formatted.clone()
+    }
+    pub fn get_street_address(&self) -> Option<String> {
+        self.street_address.clone()
+    }
+    pub fn get_locality(&self) -> Option<String> {
+        self.locality.clone()
+    }
+    pub fn get_region(&self) -> Option<String> {
+        self.region.clone()
+    }
+    pub fn get_postal_code(&self) -> Option<String> {
+        self.postal_code.clone()
+    }
+    pub fn get_country(&self) -> Option<String> {
+        self.country.clone()
+    }
+}
+
+/// Some issuers send `"true"` instead of `true` for the `*_verified` claims.
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+    match Option::<BoolOrString>::deserialize(deserializer)? {
+        Some(BoolOrString::Bool(value)) => Ok(Some(value)),
+        Some(BoolOrString::String(value)) => match value.as_str() {
+            "true" => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            _ => Err(D::Error::custom("expected a boolean")),
+        },
+        None => Ok(None),
+    }
+}
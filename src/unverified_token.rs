@@ -1,22 +1,25 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::Deserialize;
 
+use crate::certificate_chain::CertificateChainPolicy;
+use crate::key_archive::KeyArchive;
 #[cfg(feature = "async")]
 use crate::key_provider::AsyncKeyProvider;
 #[cfg(feature = "blocking")]
 use crate::key_provider::KeyProvider;
+use crate::token::RawClaims;
 use crate::{base64_decode, header::Header, jwk::JsonWebKey, Error, RequiredClaims, Token};
 
 pub struct UnverifiedToken<P> {
     header: Header,
-    signed_body: String,
     signature: Vec<u8>,
     claims: RequiredClaims,
     json_payload: P,
+    raw_claims: RawClaims,
+    raw_token: String,
+    // The header and payload segments at the start of `raw_token`, which the signature covers.
+    signed_len: usize,
 }
 
 impl<P> UnverifiedToken<P>
@@ -27,22 +30,44 @@ where
         token_string: &str,
         check_expiration: bool,
         client_id: &str,
+        issuers: &[String],
+    ) -> Result<Self, Error> {
+        let token = UnverifiedToken::decode(token_string, check_expiration, client_id, issuers)?;
+        let json_payload = serde_json::from_slice(&token.json_payload)?;
+        Ok(Self {
+            header: token.header,
+            signature: token.signature,
+            claims: token.claims,
+            json_payload,
+            raw_claims: RawClaims::new(token.json_payload),
+            raw_token: token.raw_token,
+            signed_len: token.signed_len,
+        })
+    }
+}
+
+impl UnverifiedToken<Vec<u8>> {
+    /// Checks everything but the signature, and keeps the payload as decoded JSON.
+    pub fn decode(
+        token_string: &str,
+        check_expiration: bool,
+        client_id: &str,
+        issuers: &[String],
     ) -> Result<Self, Error> {
         let mut segments = token_string.split('.');
         let encoded_header = segments.next().ok_or(Error::InvalidToken)?;
         let encoded_payload = segments.next().ok_or(Error::InvalidToken)?;
         let encoded_signature = segments.next().ok_or(Error::InvalidToken)?;
 
-        let header: Header = serde_json::from_slice(&base64_decode(&encoded_header)?)?;
-        let signed_body = format!("{}.{}", encoded_header, encoded_payload);
-        let signature = base64_decode(&encoded_signature)?;
-        let payload = base64_decode(&encoded_payload)?;
+        let header: Header = serde_json::from_slice(&base64_decode(encoded_header)?)?;
+        let signed_len = encoded_header.len() + 1 + encoded_payload.len();
+        let signature = base64_decode(encoded_signature)?;
+        let payload = base64_decode(encoded_payload)?;
         let claims: RequiredClaims = serde_json::from_slice(&payload)?;
         if claims.get_audience() != client_id {
             return Err(Error::InvalidToken);
         }
-        let issuer = claims.get_issuer();
-        if issuer != "https://accounts.google.com" && issuer != "accounts.google.com" {
+        if !issuers.contains(&claims.get_issuer()) {
             return Err(Error::InvalidToken);
         }
         let current_timestamp = SystemTime::now()
@@ -55,30 +80,64 @@ where
         if claims.get_issued_at() > claims.get_expires_at() {
             return Err(Error::InvalidToken);
         }
-        let json_payload: P = serde_json::from_slice(&payload)?;
         Ok(Self {
             claims,
             signature,
-            signed_body,
-            json_payload,
+            json_payload: payload,
             header,
+            raw_claims: RawClaims::default(),
+            raw_token: token_string.to_owned(),
+            signed_len,
         })
     }
 }
 
 impl<P> UnverifiedToken<P> {
     #[cfg(feature = "blocking")]
-    pub fn verify<KP: KeyProvider>(self, key_provider: &Arc<Mutex<KP>>) -> Result<Token<P>, Error> {
-        let key_id = self.header.key_id.clone();
-        self.verify_with_key(key_provider.lock().unwrap().get_key(&key_id))
+    pub fn verify<KP: KeyProvider>(
+        self,
+        key_provider: &KP,
+        chain_policy: Option<&CertificateChainPolicy>,
+    ) -> Result<Token<P>, Error> {
+        if let Some(key) = self.certificate_chain_key(chain_policy) {
+            return self.verify_with_key(Ok(Some(key?)));
+        }
+        let key_id = self.key_id()?;
+        self.verify_with_key(key_provider.get_key(&key_id))
     }
     #[cfg(feature = "async")]
     pub async fn verify_async<KP: AsyncKeyProvider>(
         self,
-        key_provider: &Arc<Mutex<KP>>,
+        key_provider: &KP,
+        chain_policy: Option<&CertificateChainPolicy>,
     ) -> Result<Token<P>, Error> {
-        let key_id = self.header.key_id.clone();
-        self.verify_with_key(key_provider.lock().unwrap().get_key_async(&key_id).await)
+        if let Some(key) = self.certificate_chain_key(chain_policy) {
+            return self.verify_with_key(Ok(Some(key?)));
+        }
+        let key_id = self.key_id()?;
+        self.verify_with_key(key_provider.get_key_async(&key_id).await)
+    }
+    /// Verifies against the archived key with the token's key id, if that key was current when
+    /// the token was issued.
+    pub fn verify_archived<A: KeyArchive + ?Sized>(self, archive: &A) -> Result<Token<P>, Error> {
+        let issued_at = UNIX_EPOCH + Duration::from_secs(self.claims.get_issued_at());
+        let key = archive
+            .get(&self.key_id()?)
+            .filter(|archived| archived.was_current_at(issued_at))
+            .map(|archived| archived.key);
+        self.verify_with_key(Ok(key))
+    }
+    fn key_id(&self) -> Result<String, Error> {
+        self.header.key_id.clone().ok_or(Error::InvalidToken)
+    }
+    /// The leaf key of the token's `x5c` chain, if it has one and the client trusts chains.
+    fn certificate_chain_key(
+        &self,
+        chain_policy: Option<&CertificateChainPolicy>,
+    ) -> Option<Result<JsonWebKey, Error>> {
+        let chain = self.header.certificate_chain.as_ref()?;
+        let key_id = self.header.key_id.as_deref().unwrap_or_default();
+        Some(chain_policy?.leaf_key(key_id, chain))
     }
     fn verify_with_key(self, key: Result<Option<JsonWebKey>, ()>) -> Result<Token<P>, Error> {
         let key = match key {
@@ -86,7 +145,28 @@ impl<P> UnverifiedToken<P> {
             Ok(None) => return Err(Error::InvalidToken),
             Err(_) => return Err(Error::RetrieveKeyFailure),
         };
-        key.verify(self.signed_body.as_bytes(), &self.signature)?;
-        Ok(Token::new(self.claims, self.json_payload))
+        if !key.accepts_issuer(&self.claims.get_issuer()) {
+            return Err(Error::InvalidToken);
+        }
+        let signed_body = &self.raw_token[..self.signed_len];
+        verify_signature(&key, &self.header, signed_body, &self.signature)?;
+        Ok(Token::new(self.claims, self.json_payload).with_source(
+            self.header,
+            self.raw_claims,
+            self.raw_token,
+        ))
+    }
+}
+
+/// Checks the signature with the header's algorithm, or the key's if the header has none.
+pub(crate) fn verify_signature(
+    key: &JsonWebKey,
+    header: &Header,
+    signed_body: &str,
+    signature: &[u8],
+) -> Result<(), Error> {
+    match header.algorithm {
+        Some(algorithm) => key.verify_with_algorithm(algorithm, signed_body.as_bytes(), signature),
+        None => key.verify(signed_body.as_bytes(), signature),
     }
 }
@@ -0,0 +1,121 @@
+use crate::jwk::{JsonWebKey, JsonWebKeySet};
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::x509::{X509Ref, X509};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parses the `{"<key id>": "<PEM certificate>"}` format Google uses for its X.509 endpoints.
+/// Certificates that cannot be used, or are outside their validity period, are left out.
+pub fn parse_certificate_map(text: &str) -> Result<JsonWebKeySet, ()> {
+    let certificates: HashMap<String, String> = serde_json::from_str(text).map_err(|_| ())?;
+    let keys = certificates
+        .iter()
+        .filter_map(|(key_id, pem)| certificate_key(key_id, pem))
+        .collect();
+    Ok(JsonWebKeySet::new(keys))
+}
+
+/// The RS256 key of a certificate that is valid now. It expires with the certificate.
+fn certificate_key(key_id: &str, pem: &str) -> Option<JsonWebKey> {
+    let certificate = X509::from_pem(pem.as_bytes()).ok()?;
+    if !is_valid_now(&certificate).ok()? {
+        return None;
+    }
+    let public_key = certificate.public_key().ok()?;
+    let key = JsonWebKey::from_rsa_public_key(key_id, public_key).ok()?;
+    Some(key.with_valid_until(to_system_time(certificate.not_after()).ok()?))
+}
+
+pub fn is_valid_now(certificate: &X509Ref) -> Result<bool, ()> {
+    let now = Asn1Time::days_from_now(0).map_err(|_| ())?;
+    Ok(certificate.not_before() <= now && certificate.not_after() >= now)
+}
+
+fn to_system_time(time: &Asn1TimeRef) -> Result<SystemTime, ()> {
+    let since_epoch = Asn1Time::from_unix(0)
+        .and_then(|epoch| epoch.diff(time))
+        .map_err(|_| ())?;
+    let seconds = i64::from(since_epoch.days) * 86400 + i64::from(since_epoch.secs);
+    Ok(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).map_err(|_| ())?))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::parse_certificate_map;
+    use crate::jwk::JsonWebKeySet;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::time::Duration;
+
+    pub fn certificate(key: &PKey<Private>, not_before: i64, not_after: i64) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "test").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(not_before).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(not_after).unwrap())
+            .unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_parse_certificate_map() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let valid = certificate(&key, now - 3600, now + 3600);
+        let expired = certificate(&key, now - 7200, now - 3600);
+        let text = serde_json::json!({
+            "valid": String::from_utf8(valid.to_pem().unwrap()).unwrap(),
+            "expired": String::from_utf8(expired.to_pem().unwrap()).unwrap(),
+        })
+        .to_string();
+
+        let key_set = parse_certificate_map(&text).unwrap();
+        assert!(key_set.get_key("valid").is_some());
+        assert!(key_set.get_key("expired").is_none());
+        assert!(parse_certificate_map("not a map").is_err());
+    }
+
+    #[test]
+    fn test_unusable_certificates_are_skipped() {
+        let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let ec = EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap());
+        let ec = PKey::from_ec_key(ec.unwrap()).unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let text = serde_json::json!({
+            "valid": String::from_utf8(certificate(&rsa, now - 60, now + 3600).to_pem().unwrap()).unwrap(),
+            "ec": String::from_utf8(certificate(&ec, now - 60, now + 3600).to_pem().unwrap()).unwrap(),
+            "broken": "not a certificate",
+        })
+        .to_string();
+
+        let key_set = parse_certificate_map(&text).unwrap();
+        assert_eq!(key_set.get_key_ids(), vec!["valid".to_owned()]);
+        let key = key_set.get_key("valid").unwrap();
+        assert!(!key.has_expired());
+        // Cached past the certificate's expiry.
+        let expired = key.with_valid_until(std::time::SystemTime::now() - Duration::from_secs(1));
+        assert!(expired.has_expired());
+        assert!(JsonWebKeySet::new(vec![expired]).get_key("valid").is_none());
+    }
+}